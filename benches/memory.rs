@@ -0,0 +1,65 @@
+/*
+ * Compare the dense and sparse memory backends on the puzzle inputs:
+ *
+ *   cargo bench --bench memory
+ *
+ * Day 2 forks the program for every noun and verb, so mostly measures
+ * copy-on-write and page table writes, while Day 5 mostly measures reads.
+ */
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+
+use advent_of_code_2019_rust::intcode::memory::Backend;
+use advent_of_code_2019_rust::intcode::{parse, Machine};
+
+const BACKENDS: [(&str, Backend); 2] = [("dense", Backend::Dense), ("sparse", Backend::Sparse)];
+
+fn machine(program: &str, backend: Backend) -> Machine {
+    let mut machine = Machine::new(&parse(program));
+    machine.set_backend(backend);
+    machine
+}
+
+fn day2(c: &mut Criterion) {
+    let mut group = c.benchmark_group("day2 search");
+    for &(name, backend) in &BACKENDS {
+        let loaded = machine(include_str!("../input/2019/day2.txt"), backend);
+        group.bench_function(BenchmarkId::from_parameter(name), |b| {
+            b.iter(|| {
+                for noun in 0..100 {
+                    for verb in 0..100 {
+                        let mut attempt = loaded.fork();
+                        attempt.write(1, noun);
+                        attempt.write(2, verb);
+                        if attempt.run_to_halt().is_ok() && attempt.read(0) == 19_690_720 {
+                            return 100 * noun + verb;
+                        }
+                    }
+                }
+                unreachable!()
+            })
+        });
+    }
+    group.finish();
+}
+
+fn day5(c: &mut Criterion) {
+    let mut group = c.benchmark_group("day5");
+    for &(name, backend) in &BACKENDS {
+        let loaded = machine(include_str!("../input/2019/day5.txt"), backend);
+        for &input in &[1, 5] {
+            let id = BenchmarkId::new(name, format!("input {}", input));
+            group.bench_with_input(id, &input, |b, &input| {
+                b.iter(|| {
+                    let mut machine = loaded.fork();
+                    machine.push_input(input);
+                    machine.run_to_halt().unwrap()
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, day2, day5);
+criterion_main!(benches);
@@ -0,0 +1,43 @@
+/*
+ * Open a core dump written by `intcode --core`:
+ *
+ *   intcode-core <dump> [<count>]
+ *
+ * Prints the fault, any input that was still queued, the instructions
+ * executed before the fault, and a disassembly of count instructions (10 by
+ * default) starting at the faulting address.
+ */
+
+use std::env;
+use std::error::Error;
+use std::process;
+
+use advent_of_code_2019_rust::intcode::dump::CoreDump;
+use advent_of_code_2019_rust::intcode::opcode::Registry;
+
+const USAGE: &str = "usage: intcode-core <dump> [<count>]";
+
+fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let (path, count) = match args {
+        [path] => (path, 10),
+        [path, count] => (
+            path,
+            count
+                .parse()
+                .map_err(|_| format!("invalid count: {:?}", count))?,
+        ),
+        _ => return Err(USAGE.into()),
+    };
+
+    let dump = CoreDump::load(path).map_err(|e| format!("{}: {}", path, e))?;
+    println!("{}", dump.listing(&Registry::standard(), count));
+    Ok(())
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if let Err(error) = run(&args) {
+        eprintln!("{}", error);
+        process::exit(2);
+    }
+}
@@ -0,0 +1,22 @@
+/*
+ * Debug Intcode assembler source from an editor, using the Debug Adapter
+ * Protocol over stdin and stdout:
+ *
+ *   intcode-dap
+ *
+ * See `intcode::dap` for the launch arguments and what's supported.
+ */
+
+use std::io;
+use std::process;
+
+use advent_of_code_2019_rust::intcode::dap::Adapter;
+use advent_of_code_2019_rust::intcode::opcode::Registry;
+
+fn main() {
+    let mut adapter = Adapter::new(Registry::standard());
+    if let Err(error) = adapter.serve(io::stdin().lock(), io::stdout().lock()) {
+        eprintln!("{}", error);
+        process::exit(2);
+    }
+}
@@ -0,0 +1,66 @@
+/*
+ * Show which memory addresses differ between two Intcode memory snapshots,
+ * or between a program and the memory it halts with:
+ *
+ *   intcode-diff <before> <after>
+ *   intcode-diff --run <program> [<input>...]
+ *
+ * Exits with 0 if nothing changed, 1 if something did, and 2 on errors.
+ */
+
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::process;
+
+use advent_of_code_2019_rust::intcode::diff::diff;
+use advent_of_code_2019_rust::intcode::parser::{parse_with, Options};
+use advent_of_code_2019_rust::intcode::{Machine, Word};
+
+const USAGE: &str =
+    "usage: intcode-diff <before> <after>\n       intcode-diff --run <program> [<input>...]";
+
+fn load(path: &str) -> Result<Vec<Word>, Box<dyn Error>> {
+    let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    Ok(parse_with(&text, &Options::lenient()).map_err(|e| format!("{}: {}", path, e))?)
+}
+
+fn run(args: &[String]) -> Result<bool, Box<dyn Error>> {
+    let (before, after) = match args {
+        [flag, program, input @ ..] if flag == "--run" => {
+            let before = load(program)?;
+            let mut machine = Machine::new(&before);
+            for value in input {
+                machine.push_input(
+                    value
+                        .parse()
+                        .map_err(|_| format!("invalid input: {:?}", value))?,
+                );
+            }
+            for value in machine.run_to_halt()? {
+                println!("output: {}", value);
+            }
+            (before, machine.memory().to_vec())
+        }
+        [before, after] => (load(before)?, load(after)?),
+        _ => return Err(USAGE.into()),
+    };
+
+    let diff = diff(&before, &after);
+    if !diff.is_empty() {
+        println!("{}", diff);
+    }
+
+    Ok(!diff.is_empty())
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    match run(&args) {
+        Ok(changed) => process::exit(changed as i32),
+        Err(error) => {
+            eprintln!("{}", error);
+            process::exit(2);
+        }
+    }
+}
@@ -0,0 +1,387 @@
+/*
+ * Run an Intcode program file:
+ *
+ *   intcode [options] <program>
+ *
+ *   --input <v>[,<v>...]  queue input values (may be repeated)
+ *   --patch <addr>=<v>    write a value before running, like Day 2's noun and
+ *                         verb (may be repeated)
+ *   --trace               log every executed instruction to stderr
+ *   --chrome-trace <file> write the run to a file as Chrome trace-event JSON,
+ *                         with basic blocks as spans and I/O as instant events
+ *   --explain <n>         after the run, show how output n (from 0) was
+ *                         derived from the program and inputs on stderr
+ *   --taint               after the run, report which inputs each output
+ *                         depended on to stderr
+ *   --max-steps <n>       stop after executing n instructions
+ *   --memory              print the final memory after the outputs, or with
+ *                         --sparse, each page of it as `<address>: <words>`
+ *   --strict              reject ignored parameter modes
+ *   --sparse              use sparse memory, for programs that write to huge
+ *                         addresses
+ *   --level <level>       limit the instruction set to day2, day5 or full
+ *                         (the default)
+ *   --explore <v>[,<v>...]
+ *                         once the queued input runs out, try every path
+ *                         through the program with these input values and
+ *                         report the outputs of each, allowing 10000 steps
+ *                         per path unless --max-steps is given
+ *   --max-paths <n>       stop exploring after n paths (default 1000)
+ *   --gdb <port>          wait for a debugger to connect on 127.0.0.1:<port>
+ *                         and let it control the run with the GDB remote
+ *                         protocol
+ *   --core <file>         if the program faults, write a core dump with the
+ *                         last 20 executed instructions to this file; open it
+ *                         with `intcode-core`
+ *   --record <file>       record the run's inputs and outputs to this file,
+ *                         reading input from stdin once the queued input runs
+ *                         out, so an interactive session can be replayed
+ *   --replay <file>       run the program through a recorded session instead,
+ *                         checking it produces the same outputs
+ *
+ * Outputs are printed one per line, followed by a report of how the run ended
+ * on stderr. Exits with 0 when the program halts, 1 when it faults or runs out
+ * of input, 2 on usage or file errors, and 3 when it reaches the step limit.
+ * A replay exits with 0 if the run matched the session and 1 if it didn't.
+ *
+ * --replay, --explore and --gdb replace the ordinary run, so they can't be
+ * combined with each other, or with --record, --trace, --chrome-trace,
+ * --explain, --taint, --memory or --core.
+ */
+
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::net::TcpListener;
+use std::process;
+
+use advent_of_code_2019_rust::intcode::dump::CoreDump;
+use advent_of_code_2019_rust::intcode::exit::{ExitReason, ExitReport};
+use advent_of_code_2019_rust::intcode::explore::explore;
+use advent_of_code_2019_rust::intcode::gdb::Stub;
+use advent_of_code_2019_rust::intcode::memory::Backend;
+use advent_of_code_2019_rust::intcode::opcode::Level;
+use advent_of_code_2019_rust::intcode::parser::{parse_with, Options};
+use advent_of_code_2019_rust::intcode::provenance::explain;
+use advent_of_code_2019_rust::intcode::session::Session;
+use advent_of_code_2019_rust::intcode::trace::{ChromeTrace, TraceEntry};
+use advent_of_code_2019_rust::intcode::{Event, IntcodeError, Machine, Word};
+
+const USAGE: &str = "usage: intcode [--input <v>[,<v>...]] [--patch <addr>=<v>] [--trace] [--chrome-trace <file>] [--explain <n>] [--taint] [--max-steps <n>] [--memory] [--strict] [--sparse] [--level <level>] [--explore <v>[,<v>...]] [--max-paths <n>] [--gdb <port>] [--core <file>] [--record <file>] [--replay <file>] <program>";
+
+#[derive(Default)]
+struct Config {
+    program: String,
+    input: Vec<Word>,
+    patches: Vec<(usize, Word)>,
+    trace: bool,
+    chrome_trace: Option<String>,
+    explain: Option<usize>,
+    taint: bool,
+    max_steps: Option<u64>,
+    memory: bool,
+    strict: bool,
+    sparse: bool,
+    level: Level,
+    explore: Option<Vec<Word>>,
+    max_paths: Option<usize>,
+    core: Option<String>,
+    gdb: Option<u16>,
+    record: Option<String>,
+    replay: Option<String>,
+}
+
+// How many executed instructions a core dump keeps.
+const CORE_HISTORY: usize = 20;
+
+fn number<T: std::str::FromStr>(text: &str, what: &str) -> Result<T, String> {
+    text.trim()
+        .parse()
+        .map_err(|_| format!("invalid {}: {:?}", what, text))
+}
+
+fn parse_args(args: &[String]) -> Result<Config, String> {
+    let mut config = Config::default();
+    let mut program = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("{} needs a value", arg));
+        match arg.as_str() {
+            "--input" => {
+                for v in value()?.split(',') {
+                    config.input.push(number(v, "input")?);
+                }
+            }
+            "--patch" => {
+                let patch = value()?;
+                let (address, v) = patch
+                    .split_once('=')
+                    .ok_or_else(|| format!("invalid patch: {:?}", patch))?;
+                config
+                    .patches
+                    .push((number(address, "address")?, number(v, "value")?));
+            }
+            "--explore" => {
+                let values: Result<Vec<Word>, String> =
+                    value()?.split(',').map(|v| number(v, "value")).collect();
+                config.explore = Some(values?);
+            }
+            "--max-paths" => config.max_paths = Some(number(value()?, "path count")?),
+            "--gdb" => config.gdb = Some(number(value()?, "port")?),
+            "--core" => config.core = Some(value()?.clone()),
+            "--record" => config.record = Some(value()?.clone()),
+            "--replay" => config.replay = Some(value()?.clone()),
+            "--level" => config.level = value()?.parse()?,
+            "--max-steps" => config.max_steps = Some(number(value()?, "step count")?),
+            "--trace" => config.trace = true,
+            "--chrome-trace" => config.chrome_trace = Some(value()?.clone()),
+            "--explain" => config.explain = Some(number(value()?, "output number")?),
+            "--taint" => config.taint = true,
+            "--memory" => config.memory = true,
+            "--strict" => config.strict = true,
+            "--sparse" => config.sparse = true,
+            _ if arg.starts_with("--") => return Err(format!("unknown option: {}", arg)),
+            _ if program.is_none() => program = Some(arg.clone()),
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+
+    config.program = program.ok_or(USAGE)?;
+    if config.max_paths.is_some() && config.explore.is_none() {
+        return Err("--max-paths needs --explore".to_string());
+    }
+
+    // These replace the ordinary run, so can't be used with each other or
+    // with options that only affect it. A replay also supplies its own input.
+    let modes = [
+        ("--replay", config.replay.is_some()),
+        ("--explore", config.explore.is_some()),
+        ("--gdb", config.gdb.is_some()),
+    ];
+    if let Some(&(mode, _)) = modes.iter().find(|(_, given)| *given) {
+        let ignored = [
+            ("--input", mode == "--replay" && !config.input.is_empty()),
+            ("--record", config.record.is_some()),
+            ("--trace", config.trace),
+            ("--chrome-trace", config.chrome_trace.is_some()),
+            ("--explain", config.explain.is_some()),
+            ("--taint", config.taint),
+            ("--memory", config.memory),
+            ("--core", config.core.is_some()),
+        ];
+        let conflict = modes
+            .iter()
+            .filter(|(option, _)| *option != mode)
+            .chain(&ignored)
+            .find(|(_, given)| *given);
+        if let Some((option, _)) = conflict {
+            return Err(format!("{} can't be used with {}", option, mode));
+        }
+    }
+
+    Ok(config)
+}
+
+fn load(path: &str) -> Result<Vec<Word>, Box<dyn Error>> {
+    let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    Ok(parse_with(&text, &Options::lenient()).map_err(|e| format!("{}: {}", path, e))?)
+}
+
+// Returns the trace too, if it's needed to explain an output.
+fn run(
+    config: &Config,
+    machine: &mut Machine,
+    mut chrome: Option<&mut ChromeTrace>,
+) -> (ExitReport, Vec<TraceEntry>) {
+    let mut kept = Vec::new();
+
+    let reason = loop {
+        let event = machine.step();
+
+        for entry in machine.take_trace() {
+            if config.trace {
+                eprintln!("{}", entry.format(machine.registry()));
+            }
+            if let Some(chrome) = chrome.as_mut() {
+                chrome.push(&entry);
+            }
+            if config.explain.is_some() {
+                kept.push(entry);
+            }
+        }
+
+        match event {
+            Ok(Some(Event::Output(value))) => println!("{}", value),
+            Ok(Some(Event::NeedInput)) if config.record.is_some() => match prompt() {
+                Some(value) => machine.push_input(value),
+                None => break ExitReason::NeedInput,
+            },
+            Ok(Some(Event::NeedInput)) => break ExitReason::NeedInput,
+            Ok(Some(Event::Halted)) if machine.ran_off_end() => break ExitReason::RanOffEnd,
+            Ok(Some(Event::Halted)) => break ExitReason::Halted,
+            Ok(None) => {}
+            Err(IntcodeError::StepLimit { .. }) => break ExitReason::StepLimit,
+            Err(error) => break ExitReason::Fault(error),
+        }
+    };
+
+    (machine.report(reason), kept)
+}
+
+// Ask for an input value on stdin, until one parses or stdin ends.
+fn prompt() -> Option<Word> {
+    let stdin = io::stdin();
+    loop {
+        eprint!("input: ");
+        io::stderr().flush().ok()?;
+
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line).ok()? == 0 {
+            return None;
+        }
+        match number(line.trim(), "input") {
+            Ok(value) => return Some(value),
+            Err(error) => eprintln!("{}", error),
+        }
+    }
+}
+
+fn debug(machine: Machine, port: u16) -> Result<(), Box<dyn Error>> {
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    eprintln!("waiting for a debugger on {}", listener.local_addr()?);
+    let (stream, address) = listener.accept()?;
+    stream.set_nodelay(true)?;
+    eprintln!("debugging for {}", address);
+
+    let mut stub = Stub::new(machine);
+    stub.serve(stream)?;
+    for value in stub.outputs() {
+        println!("{}", value);
+    }
+
+    eprintln!("debugger detached at address {}", stub.machine().pointer());
+    Ok(())
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let config = parse_args(&args).unwrap_or_else(|error| {
+        eprintln!("{}", error);
+        process::exit(2);
+    });
+    let program = load(&config.program).unwrap_or_else(|error| {
+        eprintln!("{}", error);
+        process::exit(2);
+    });
+
+    let mut machine = Machine::new(&program);
+    if config.sparse {
+        machine.set_backend(Backend::Sparse);
+    }
+    for &(address, value) in &config.patches {
+        if !machine.memory().fits(address) {
+            eprintln!("can't patch address {} without --sparse", address);
+            process::exit(2);
+        }
+        machine.write(address, value);
+    }
+    machine.extend_input(config.input.iter().copied());
+    machine.set_step_limit(config.max_steps);
+    machine.set_strict(config.strict);
+    machine.set_level(config.level);
+    machine.set_trace(config.trace || config.chrome_trace.is_some() || config.explain.is_some());
+    machine.set_taint(config.taint);
+    machine.set_recording(config.record.is_some());
+    if config.core.is_some() {
+        machine.set_history(CORE_HISTORY);
+    }
+
+    if let Some(path) = &config.replay {
+        let session = Session::load(path).unwrap_or_else(|error| {
+            eprintln!("{}: {}", path, error);
+            process::exit(2);
+        });
+        if let Err(error) = session.replay(&mut machine) {
+            eprintln!("{}", error);
+            process::exit(1);
+        }
+        eprintln!("replayed {} records from {}", session.records.len(), path);
+        return;
+    }
+
+    if let Some(values) = &config.explore {
+        let exploration = explore(
+            &machine,
+            values,
+            config.max_steps.unwrap_or(10_000),
+            config.max_paths.unwrap_or(1000),
+        );
+        println!("{}", exploration);
+        return;
+    }
+
+    if let Some(port) = config.gdb {
+        debug(machine, port).unwrap_or_else(|error| {
+            eprintln!("{}", error);
+            process::exit(2);
+        });
+        return;
+    }
+
+    let mut chrome = config.chrome_trace.as_ref().map(|_| ChromeTrace::new());
+    let (report, trace) = run(&config, &mut machine, chrome.as_mut());
+    if let (Some(path), Some(chrome)) = (&config.chrome_trace, chrome) {
+        if let Err(error) = fs::write(path, chrome.finish().to_string()) {
+            eprintln!("{}: {}", path, error);
+        }
+    }
+
+    if let Some(path) = &config.record {
+        let session = Session::from_machine(&machine).unwrap();
+        if let Err(error) = session.save(path) {
+            eprintln!("{}: {}", path, error);
+        }
+    }
+
+    if config.memory {
+        let join = |words: &[Word]| {
+            let words: Vec<String> = words.iter().map(Word::to_string).collect();
+            words.join(",")
+        };
+        match machine.memory().backend() {
+            Backend::Dense => println!("{}", join(&machine.memory().to_vec())),
+            // Only the pages it has, as it could span a huge address space.
+            Backend::Sparse => {
+                for (address, words) in machine.memory().pages() {
+                    println!("{}: {}", address, join(words));
+                }
+            }
+        }
+    }
+
+    if let Some(output) = config.explain {
+        match explain(machine.registry(), &trace, output) {
+            Some(explanation) => eprint!("{}", explanation),
+            None => eprintln!("no output {} to explain", output),
+        }
+    }
+    if let Some(taint) = machine.taint() {
+        for output in taint.outputs() {
+            eprintln!("{}", output);
+        }
+    }
+    eprintln!("{}", report);
+    if let (Some(path), ExitReason::Fault(error)) = (&config.core, &report.reason) {
+        match CoreDump::new(&machine, error).save(path) {
+            Ok(()) => eprintln!("core dumped to {}", path),
+            Err(e) => eprintln!("{}: {}", path, e),
+        }
+    }
+    process::exit(match report.reason {
+        ExitReason::Halted | ExitReason::RanOffEnd => 0,
+        ExitReason::StepLimit => 3,
+        ExitReason::Fault(_) | ExitReason::NeedInput => 1,
+    });
+}
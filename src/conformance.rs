@@ -0,0 +1,202 @@
+/*
+ * File-driven Intcode conformance tests.
+ *
+ * Every `*.txt` file in `tests/intcode/` is a test vector describing a single
+ * run of a program, so adding a regression case means adding a file:
+ *
+ *   # Comments and blank lines are ignored.
+ *   program: 3,0,4,0,99
+ *   input: 7
+ *   output: 7
+ *   memory: 7,0,4,0,99
+ *
+ * `program` and at least one expectation are required. `input` is consumed in
+ * order by the input instructions. The expectations `output` and `memory` are
+ * only checked when present, and an empty `output:` expects no output at all.
+ * `error` is the error the interpreter reports, or the message it panics
+ * with, and a vector without it expects the run to succeed.
+ */
+
+use std::fs;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+
+use crate::day5;
+use crate::intcode::diff::diff;
+use crate::intcode::parser::{parse_with, Options};
+
+#[derive(Debug, Default)]
+struct TestVector {
+    program: Vec<i32>,
+    input: Vec<i32>,
+    output: Option<Vec<i32>>,
+    memory: Option<Vec<i32>>,
+    error: Option<String>,
+}
+
+#[derive(Debug, Default)]
+struct Outcome {
+    output: Vec<i32>,
+    memory: Vec<i32>,
+    error: Option<String>,
+}
+
+fn vector_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/intcode")
+}
+
+fn discover() -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = fs::read_dir(vector_dir())
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "txt"))
+        .collect();
+    paths.sort();
+
+    paths
+}
+
+fn parse_words(value: &str) -> Result<Vec<i32>, String> {
+    parse_with(value, &Options::default()).map_err(|e| e.to_string())
+}
+
+fn parse(text: &str) -> Result<TestVector, String> {
+    let mut vector = TestVector::default();
+    let mut has_program = false;
+
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let mut parts = line.splitn(2, ':');
+        let key = parts.next().unwrap().trim();
+        let value = match parts.next() {
+            Some(value) => value.trim(),
+            None => return Err(format!("line {}: expected `key: value`", number + 1)),
+        };
+
+        let words = || parse_words(value).map_err(|e| format!("line {}: {}", number + 1, e));
+        match key {
+            "program" => {
+                vector.program = words()?;
+                has_program = true;
+            }
+            "input" => vector.input = words()?,
+            "output" => vector.output = Some(words()?),
+            "memory" => vector.memory = Some(words()?),
+            "error" => vector.error = Some(value.to_string()),
+            _ => return Err(format!("line {}: unknown key: {:?}", number + 1, key)),
+        }
+    }
+
+    if !has_program {
+        return Err("missing `program`".to_string());
+    }
+    if vector.output.is_none() && vector.memory.is_none() && vector.error.is_none() {
+        return Err("no expectations, need one of `output`, `memory` or `error`".to_string());
+    }
+
+    Ok(vector)
+}
+
+fn run(vector: &TestVector) -> Outcome {
+    let mut outcome = Outcome {
+        memory: vector.program.clone(),
+        ..Default::default()
+    };
+    let mut input = vector.input.iter();
+
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        let memory = &mut outcome.memory;
+        let mut pointer = 0;
+
+        while pointer < memory.len() && memory[pointer] != 99 {
+            let value = if memory[pointer] % 100 == 3 {
+                input.next().copied()
+            } else {
+                None
+            };
+
+            match day5::do_instruction(memory, &mut pointer, value) {
+                Ok(Some(value)) => outcome.output.push(value.parse().unwrap()),
+                Ok(None) => {}
+                Err(error) => {
+                    outcome.error = Some(error.to_string());
+                    break;
+                }
+            }
+        }
+    }));
+
+    if let Err(payload) = result {
+        outcome.error = Some(
+            payload
+                .downcast_ref::<String>()
+                .cloned()
+                .or_else(|| payload.downcast_ref::<&str>().map(|s| s.to_string()))
+                .unwrap_or_else(|| "unknown panic".to_string()),
+        );
+    }
+
+    outcome
+}
+
+fn check(vector: &TestVector, outcome: &Outcome) -> Vec<String> {
+    let mut mismatches = Vec::new();
+
+    if let Some(output) = &vector.output {
+        if *output != outcome.output {
+            mismatches.push(format!(
+                "output: expected {:?}, got {:?}",
+                output, outcome.output
+            ));
+        }
+    }
+
+    if let Some(memory) = &vector.memory {
+        let diff = diff(memory, &outcome.memory);
+        if !diff.is_empty() {
+            mismatches.push(format!("memory differs (expected -> actual):\n{}", diff));
+        }
+    }
+
+    if vector.error != outcome.error {
+        mismatches.push(format!(
+            "error: expected {:?}, got {:?}",
+            vector.error, outcome.error
+        ));
+    }
+
+    mismatches
+}
+
+#[test]
+fn vectors() {
+    let paths = discover();
+    assert!(!paths.is_empty(), "no test vectors in {:?}", vector_dir());
+
+    let mut failures = Vec::new();
+    for path in &paths {
+        let name = path.file_name().unwrap().to_string_lossy();
+        let text = fs::read_to_string(path).unwrap();
+
+        match parse(&text) {
+            Ok(vector) => {
+                for mismatch in check(&vector, &run(&vector)) {
+                    failures.push(format!("{}: {}", name, mismatch));
+                }
+            }
+            Err(error) => failures.push(format!("{}: {}", name, error)),
+        }
+    }
+
+    assert!(
+        failures.is_empty(),
+        "{} of {} test vectors failed:\n{}",
+        failures.len(),
+        paths.len(),
+        failures.join("\n")
+    );
+}
@@ -6,7 +6,7 @@ pub fn input_generator(input: &str) -> Vec<Mass> {
         .lines()
         .map(|l| {
             let mass = l.trim().parse().unwrap();
-            (mass)
+            mass
         })
         .collect()
 }
@@ -1,10 +1,11 @@
+use crate::intcode::exit::{ExitReason, ExitReport};
+use crate::intcode::opcode::Level;
+use crate::intcode::parser::{parse_with, Options};
+use crate::intcode::{IntcodeError, Machine, Word};
+
 #[aoc_generator(day2)]
 pub fn input_generator(input: &str) -> Vec<u32> {
-    input
-        .trim()
-        .split(',')
-        .map(|i| i.parse().unwrap())
-        .collect()
+    parse_with(input, &Options::lenient()).unwrap_or_else(|e| panic!("{}", e))
 }
 
 #[aoc(day2, part1)]
@@ -17,102 +18,215 @@ pub fn solve_part1(input: &[u32]) -> String {
     memory[1] = 12;
     memory[2] = 2;
 
-    execute(&mut memory);
+    let (_, report) = execute_with_report(&mut memory);
     println!("updated memory: {:?}", memory);
+    println!("program {}", report);
 
     memory[0].to_string()
 }
 
 #[aoc(day2, part2)]
-pub fn solve_part2(input: &[u32]) -> String {
-    let mut memory = vec![Default::default(); input.len()];
+pub fn solve_part2(input: &[u32]) -> Result<String, IntcodeError> {
+    let program: Vec<Word> = input.iter().map(|&i| Word::from(i)).collect();
+    let mut machine = Machine::new(&program);
+    machine.set_level(Level::Day2);
+    let mut solved = machine.fork();
 
-    let mut noun: u32 = 0;
-    let mut verb: u32 = 0;
+    let mut noun = 0;
+    let mut verb = 0;
 
     // Use some brute force to solve. It's only 100*100 iterations
-    // maximum, so will be quite fast in reality.
+    // maximum, so will be quite fast in reality. Each attempt runs on a
+    // fork of the loaded program, which only copies what it writes.
     'outer: for n in 0..=99 {
         for v in 0..=99 {
-            memory.copy_from_slice(input);
+            let mut attempt = machine.fork();
 
-            memory[1] = n;
-            memory[2] = v;
+            attempt.write(1, n);
+            attempt.write(2, v);
 
-            execute(&mut memory);
-
-            if memory[0] == 19_690_720 {
+            // A fault means the program is broken, not that this is the
+            // wrong noun and verb.
+            attempt.run_to_halt()?;
+            if attempt.read(0) == 19_690_720 {
                 noun = n;
                 verb = v;
+                solved = attempt;
                 break 'outer;
             }
         }
     }
 
     println!("noun: {:?} verb: {:?}", noun, verb);
-    println!("output: {:?}", memory[0].to_string());
+    println!("output: {:?}", solved.read(0).to_string());
 
-    (100 * noun + verb).to_string()
+    Ok((100 * noun + verb).to_string())
 }
 
-fn execute(memory: &mut [u32]) -> String {
-    let mut pointer: usize = 0;
+#[cfg(test)]
+pub(crate) fn execute(memory: &mut [u32]) -> String {
+    execute_with_report(memory).0
+}
 
-    while memory[pointer] != 99 && pointer < memory.len() {
-        pointer += do_instruction(&mut *memory, pointer);
+// Run the program, returning the final memory and why it stopped.
+pub(crate) fn execute_with_report(memory: &mut [u32]) -> (String, ExitReport) {
+    let mut pointer: usize = 0;
+    let mut steps = 0;
+    let mut fault = None;
+
+    while pointer < memory.len() && memory[pointer] != 99 {
+        match do_instruction(&mut *memory, pointer) {
+            Ok(size) => pointer += size,
+            Err(error) => {
+                fault = Some(error);
+                break;
+            }
+        }
+        steps += 1;
     }
 
-    memory
+    let reason = match fault {
+        Some(error) => ExitReason::Fault(error),
+        None if pointer < memory.len() => {
+            steps += 1;
+            ExitReason::Halted
+        }
+        None => ExitReason::RanOffEnd,
+    };
+    let report = ExitReport {
+        reason,
+        pointer,
+        steps,
+        outputs: 0,
+    };
+
+    let memory = memory
         .iter()
         .map(|&i| i.to_string())
         .collect::<Vec<String>>()
-        .join(",")
+        .join(",");
+
+    (memory, report)
 }
 
-fn do_instruction(memory: &mut [u32], pointer: usize) -> usize {
+fn do_instruction(memory: &mut [u32], pointer: usize) -> Result<usize, IntcodeError> {
     match memory[pointer] {
         1 => {
             memory[memory[pointer + 3] as usize] =
                 memory[memory[pointer + 1] as usize] + memory[memory[pointer + 2] as usize];
-            4
+            Ok(4)
         }
         2 => {
             memory[memory[pointer + 3] as usize] =
                 memory[memory[pointer + 1] as usize] * memory[memory[pointer + 2] as usize];
-            4
+            Ok(4)
         }
-        99 => 1,
-        _ => panic!(),
+        99 => Ok(1),
+        opcode => Err(IntcodeError::UnknownOpcode {
+            address: pointer,
+            opcode: Word::from(opcode),
+        }),
     }
 }
 
 #[cfg(test)]
 mod test_part1 {
     use super::*;
+    use crate::intcode::diff::assert_memory_eq;
 
     #[test]
     fn example_1() {
-        assert_eq!(execute(&mut input_generator("1,0,0,0,99")), "2,0,0,0,99");
+        let mut memory = input_generator("1,0,0,0,99");
+        execute(&mut memory);
+        assert_memory_eq(&memory, &[2, 0, 0, 0, 99]);
     }
 
     #[test]
     fn example_2() {
-        assert_eq!(execute(&mut input_generator("2,3,0,3,99")), "2,3,0,6,99");
+        let mut memory = input_generator("2,3,0,3,99");
+        execute(&mut memory);
+        assert_memory_eq(&memory, &[2, 3, 0, 6, 99]);
     }
 
     #[test]
     fn example_3() {
+        let mut memory = input_generator("2,4,4,5,99,0");
+        execute(&mut memory);
+        assert_memory_eq(&memory, &[2, 4, 4, 5, 99, 9801]);
+    }
+
+    #[test]
+    fn example_4() {
+        let mut memory = input_generator("1,1,1,4,99,5,6,0,99");
+        execute(&mut memory);
+        assert_memory_eq(&memory, &[30, 1, 1, 4, 2, 5, 6, 0, 99]);
+    }
+
+    #[test]
+    fn report() {
+        let (_, report) = execute_with_report(&mut input_generator("1,0,0,0,99"));
         assert_eq!(
-            execute(&mut input_generator("2,4,4,5,99,0")),
-            "2,4,4,5,99,9801"
+            report.to_string(),
+            "halted at address 4 after 2 steps with 0 outputs"
         );
+
+        let mut memory = input_generator("1,0,0,0");
+        let (_, report) = execute_with_report(&mut memory);
+        assert_memory_eq(&memory, &[2, 0, 0, 0]);
+        assert_eq!(report.reason, ExitReason::RanOffEnd);
     }
 
     #[test]
-    fn example_4() {
+    fn report_fault() {
+        let mut memory = input_generator("1,0,0,0,42");
+        let (_, report) = execute_with_report(&mut memory);
+        assert_memory_eq(&memory, &[2, 0, 0, 0, 42]);
+        assert_eq!(
+            report.to_string(),
+            "faulted at address 4 after 1 step with 0 outputs: unknown opcode 42 at address 4"
+        );
+    }
+
+    #[test]
+    fn day2_level() {
+        // The examples and puzzle input only use Day 2 features, and the
+        // shared machine agrees with this interpreter when limited to them.
+        let programs = [
+            "1,9,10,3,2,3,11,0,99,30,40,50",
+            "1,0,0,0,99",
+            "2,3,0,3,99",
+            "2,4,4,5,99,0",
+            "1,1,1,4,99,5,6,0,99",
+            include_str!("../input/2019/day2.txt"),
+        ];
+        for program in programs.iter() {
+            let mut memory = input_generator(program);
+            let words: Vec<Word> = memory.iter().map(|&i| Word::from(i)).collect();
+            execute(&mut memory);
+
+            let mut machine = Machine::new(&words);
+            machine.set_level(Level::Day2);
+            assert_eq!(machine.run_to_halt(), Ok(vec![]));
+
+            let expected: Vec<Word> = memory.iter().map(|&i| Word::from(i)).collect();
+            assert_memory_eq(&machine.memory().to_vec(), &expected);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_part2 {
+    use super::*;
+
+    #[test]
+    fn faults() {
+        // Opcode 42 is reached whatever the noun and verb are.
         assert_eq!(
-            execute(&mut input_generator("1,1,1,4,99,5,6,0,99")),
-            "30,1,1,4,2,5,6,0,99"
+            solve_part2(&input_generator("1,0,0,3,42")),
+            Err(IntcodeError::UnknownOpcode {
+                opcode: 42,
+                address: 4
+            })
         );
     }
 }
@@ -40,7 +40,7 @@ pub fn solve_part2(input: &[Directions]) -> u32 {
     let paths = generate_paths(input);
     let intersections = find_intersections(&paths);
 
-    let mut shortest_distance = std::u32::MAX;
+    let mut shortest_distance = u32::MAX;
     intersections
         .iter()
         .filter_map(|(&k, &v)| if v == 1 { Some(k) } else { None })
@@ -63,10 +63,7 @@ pub fn solve_part2(input: &[Directions]) -> u32 {
 
 fn generate_paths(directions: &[Directions]) -> Vec<Coordinates> {
     // Convert each direction into a path of coordinates that it has traversed.
-    directions
-        .iter()
-        .map(|i| directions_into_coordinates(&i))
-        .collect()
+    directions.iter().map(directions_into_coordinates).collect()
 }
 
 fn find_intersections(paths: &[Coordinates]) -> HashMap<Coordinate, u32> {
@@ -192,7 +189,7 @@ mod test_part1 {
     #[test]
     fn example_1() {
         assert_eq!(
-            solve_part1(&mut input_generator(
+            solve_part1(&input_generator(
                 "R75,D30,R83,U83,L12,D49,R71,U7,L72\nU62,R66,U55,R34,D71,R55,D58,R83"
             )),
             159
@@ -202,7 +199,7 @@ mod test_part1 {
     #[test]
     fn example_2() {
         assert_eq!(
-            solve_part1(&mut input_generator(
+            solve_part1(&input_generator(
                 "R98,U47,R26,D63,R33,U87,L62,D20,R33,U53,R51\nU98,R91,D20,R16,D67,R40,U7,R15,U6,R7"
             )),
             135
@@ -217,7 +214,7 @@ mod test_part2 {
     #[test]
     fn example_1() {
         assert_eq!(
-            solve_part2(&mut input_generator(
+            solve_part2(&input_generator(
                 "R75,D30,R83,U83,L12,D49,R71,U7,L72\nU62,R66,U55,R34,D71,R55,D58,R83"
             )),
             610
@@ -227,7 +224,7 @@ mod test_part2 {
     #[test]
     fn example_2() {
         assert_eq!(
-            solve_part2(&mut input_generator(
+            solve_part2(&input_generator(
                 "R98,U47,R26,D63,R33,U87,L62,D20,R33,U53,R51\nU98,R91,D20,R16,D67,R40,U7,R15,U6,R7"
             )),
             410
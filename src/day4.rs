@@ -100,17 +100,17 @@ mod test_part1 {
 
     #[test]
     fn example_1() {
-        assert_eq!(validate(111111, false), true);
+        assert!(validate(111111, false));
     }
 
     #[test]
     fn example_2() {
-        assert_eq!(validate(223450, false), false);
+        assert!(!validate(223450, false));
     }
 
     #[test]
     fn example_3() {
-        assert_eq!(validate(123789, false), false);
+        assert!(!validate(123789, false));
     }
 }
 
@@ -120,21 +120,21 @@ mod test_part2 {
 
     #[test]
     fn example_1() {
-        assert_eq!(validate(112233, true), true);
+        assert!(validate(112233, true));
     }
 
     #[test]
     fn example_2() {
-        assert_eq!(validate(123444, true), false);
+        assert!(!validate(123444, true));
     }
 
     #[test]
     fn example_3() {
-        assert_eq!(validate(111122, true), true);
+        assert!(validate(111122, true));
     }
 
     #[test]
     fn example_4() {
-        assert_eq!(validate(111223, true), true);
+        assert!(validate(111223, true));
     }
 }
@@ -1,12 +1,12 @@
 use std::io;
 
+use crate::intcode::exit::{ExitReason, ExitReport};
+use crate::intcode::parser::{parse_with, Options};
+use crate::intcode::{IntcodeError, Word};
+
 #[aoc_generator(day5)]
 pub fn input_generator(input: &str) -> Vec<i32> {
-    input
-        .trim()
-        .split(',')
-        .map(|i| i.parse().unwrap())
-        .collect()
+    parse_with(input, &Options::lenient()).unwrap_or_else(|e| panic!("{}", e))
 }
 
 #[aoc(day5, part1)]
@@ -35,11 +35,22 @@ pub fn solve_part2(input: &[i32]) -> String {
     }
 }
 
-fn run(memory: &mut [i32], input_value: i32) -> String {
+pub(crate) fn run(memory: &mut [i32], input_value: i32) -> String {
+    let (output, report) = run_with_report(memory, input_value);
+    println!("Program {}", report);
+
+    output
+}
+
+// Run the program, returning the last output and why it stopped.
+pub(crate) fn run_with_report(memory: &mut [i32], input_value: i32) -> (String, ExitReport) {
     let mut pointer: usize = 0;
+    let mut steps = 0;
+    let mut outputs = 0;
+    let mut fault = None;
 
-    let mut output = "no value".to_string();
-    while memory[pointer] != 99 && pointer < memory.len() {
+    let mut output = String::new();
+    while pointer < memory.len() && memory[pointer] != 99 {
         let input = if pointer == 0 {
             println!("Using input value: {:?}", input_value);
             Some(input_value)
@@ -47,19 +58,47 @@ fn run(memory: &mut [i32], input_value: i32) -> String {
             None
         };
 
-        if let Some(value) = do_instruction(memory, &mut pointer, input) {
-            output = value;
+        match do_instruction(memory, &mut pointer, input) {
+            Ok(Some(value)) => {
+                output = value;
+                outputs += 1;
+            }
+            Ok(None) => {}
+            Err(error) => {
+                fault = Some(error);
+                break;
+            }
         }
+        steps += 1;
     }
 
-    if memory[pointer] == 99 {
-        println!("Program halted");
-    }
-
-    output
+    let reason = match fault {
+        Some(IntcodeError::MissingInput { .. }) => ExitReason::NeedInput,
+        Some(error) => ExitReason::Fault(error),
+        None if pointer < memory.len() => {
+            steps += 1;
+            ExitReason::Halted
+        }
+        None => ExitReason::RanOffEnd,
+    };
+    let report = ExitReport {
+        reason,
+        pointer,
+        steps,
+        outputs,
+    };
+
+    (output, report)
 }
 
-fn do_instruction(memory: &mut [i32], pointer: &mut usize, input: Option<i32>) -> Option<String> {
+// Execute one instruction, returning its output if any. Unknown opcodes and
+// modes, and input instructions without a value, are returned as errors
+// before the instruction changes anything.
+pub(crate) fn do_instruction(
+    memory: &mut [i32],
+    pointer: &mut usize,
+    input: Option<i32>,
+) -> Result<Option<String>, IntcodeError> {
     // The opcode is the 2 right-most digits.
     let address = *pointer;
     let instruction = memory[address];
@@ -67,6 +106,32 @@ fn do_instruction(memory: &mut [i32], pointer: &mut usize, input: Option<i32>) -
     let mut output = None;
 
     let opcode = instruction % 100;
+    // The number of leading parameters that are read, so have a mode.
+    let reads = match opcode {
+        1 | 2 | 5 | 6 | 7 | 8 => 2,
+        4 => 1,
+        3 | 99 => 0,
+        _ => {
+            return Err(IntcodeError::UnknownOpcode {
+                address,
+                opcode: Word::from(opcode),
+            })
+        }
+    };
+    for position in 1..=reads {
+        let mode = get_parameter_mode(instruction, position);
+        if mode != 0 && mode != 1 {
+            return Err(IntcodeError::UnknownMode {
+                address,
+                position: position as usize,
+                mode: Word::from(mode),
+            });
+        }
+    }
+    if opcode == 3 && input.is_none() {
+        return Err(IntcodeError::MissingInput { address });
+    }
+
     match opcode {
         1 => opcode_add(memory, pointer),
         2 => opcode_multiply(memory, pointer),
@@ -79,10 +144,10 @@ fn do_instruction(memory: &mut [i32], pointer: &mut usize, input: Option<i32>) -
         99 => {
             *pointer += 1;
         }
-        _ => panic!("Unknown opcode: {:?}", opcode),
+        _ => unreachable!(),
     }
 
-    output
+    Ok(output)
 }
 
 // 1,a,b,c: Result of (a + b) is stored at index c.
@@ -123,13 +188,14 @@ fn opcode_add(memory: &mut [i32], pointer: &mut usize) {
 #[cfg(test)]
 mod opcode_add {
     use super::*;
+    use crate::intcode::diff::assert_memory_eq;
 
     #[test]
     fn position_mode() {
         let mut pointer = 0;
         let mut memory = input_generator("1,1,2,0,99");
         opcode_add(&mut memory, &mut pointer);
-        assert_eq!(_memory_to_string(&memory), "3,1,2,0,99");
+        assert_memory_eq(&memory, &[3, 1, 2, 0, 99]);
         assert_eq!(pointer, 4);
     }
 
@@ -138,7 +204,7 @@ mod opcode_add {
         let mut pointer = 0;
         let mut memory = input_generator("1101,2,3,0,99");
         opcode_add(&mut memory, &mut pointer);
-        assert_eq!(_memory_to_string(&memory), "5,2,3,0,99");
+        assert_memory_eq(&memory, &[5, 2, 3, 0, 99]);
         assert_eq!(pointer, 4);
     }
 }
@@ -181,13 +247,14 @@ fn opcode_multiply(memory: &mut [i32], pointer: &mut usize) {
 #[cfg(test)]
 mod opcode_multiply {
     use super::*;
+    use crate::intcode::diff::assert_memory_eq;
 
     #[test]
     fn position_mode() {
         let mut pointer = 0;
         let mut memory = input_generator("2,2,2,0,99");
         opcode_multiply(&mut memory, &mut pointer);
-        assert_eq!(_memory_to_string(&memory), "4,2,2,0,99");
+        assert_memory_eq(&memory, &[4, 2, 2, 0, 99]);
         assert_eq!(pointer, 4);
     }
 
@@ -196,7 +263,7 @@ mod opcode_multiply {
         let mut pointer = 0;
         let mut memory = input_generator("1102,2,3,0,99");
         opcode_multiply(&mut memory, &mut pointer);
-        assert_eq!(_memory_to_string(&memory), "6,2,3,0,99");
+        assert_memory_eq(&memory, &[6, 2, 3, 0, 99]);
         assert_eq!(pointer, 4);
     }
 }
@@ -235,13 +302,14 @@ fn opcode_input(memory: &mut [i32], pointer: &mut usize, input: Option<i32>) {
 #[cfg(test)]
 mod opcode_input {
     use super::*;
+    use crate::intcode::diff::assert_memory_eq;
 
     #[test]
     fn position_mode() {
         let mut pointer = 0;
         let mut memory = input_generator("3,2,0,99");
         opcode_input(&mut memory, &mut pointer, Some(1));
-        assert_eq!(_memory_to_string(&memory), "3,2,1,99");
+        assert_memory_eq(&memory, &[3, 2, 1, 99]);
         assert_eq!(pointer, 2);
     }
 
@@ -251,7 +319,7 @@ mod opcode_input {
         let mut pointer = 0;
         let mut memory = input_generator("103,2,0,99");
         opcode_input(&mut memory, &mut pointer, Some(1));
-        assert_eq!(_memory_to_string(&memory), "103,2,1,99");
+        assert_memory_eq(&memory, &[103, 2, 1, 99]);
         assert_eq!(pointer, 2);
     }
 }
@@ -281,13 +349,14 @@ fn opcode_output(memory: &mut [i32], pointer: &mut usize) -> String {
 #[cfg(test)]
 mod opcode_output {
     use super::*;
+    use crate::intcode::diff::assert_memory_eq;
 
     #[test]
     fn position_mode() {
         let mut pointer = 0;
         let mut memory = input_generator("4,2,1,99");
         let output = opcode_output(&mut memory, &mut pointer);
-        assert_eq!(_memory_to_string(&memory), "4,2,1,99");
+        assert_memory_eq(&memory, &[4, 2, 1, 99]);
         assert_eq!(output, "1");
         assert_eq!(pointer, 2);
     }
@@ -297,7 +366,7 @@ mod opcode_output {
         let mut pointer = 0;
         let mut memory = input_generator("104,2,1,99");
         let output = opcode_output(&mut memory, &mut pointer);
-        assert_eq!(_memory_to_string(&memory), "104,2,1,99");
+        assert_memory_eq(&memory, &[104, 2, 1, 99]);
         assert_eq!(output, "2");
         assert_eq!(pointer, 2);
     }
@@ -339,13 +408,14 @@ fn opcode_jump_if_true(memory: &mut [i32], pointer: &mut usize) {
 #[cfg(test)]
 mod opcode_jump_if_true {
     use super::*;
+    use crate::intcode::diff::assert_memory_eq;
 
     #[test]
     fn position_mode_true() {
         let mut pointer = 0;
         let mut memory = input_generator("5,2,1,99");
         opcode_jump_if_true(&mut memory, &mut pointer);
-        assert_eq!(_memory_to_string(&memory), "5,2,1,99");
+        assert_memory_eq(&memory, &[5, 2, 1, 99]);
         assert_eq!(pointer, 2);
     }
 
@@ -354,7 +424,7 @@ mod opcode_jump_if_true {
         let mut pointer = 0;
         let mut memory = input_generator("5,2,0,99");
         opcode_jump_if_true(&mut memory, &mut pointer);
-        assert_eq!(_memory_to_string(&memory), "5,2,0,99");
+        assert_memory_eq(&memory, &[5, 2, 0, 99]);
         assert_eq!(pointer, 3);
     }
 
@@ -363,7 +433,7 @@ mod opcode_jump_if_true {
         let mut pointer = 0;
         let mut memory = input_generator("1105,2,0,99");
         opcode_jump_if_true(&mut memory, &mut pointer);
-        assert_eq!(_memory_to_string(&memory), "1105,2,0,99");
+        assert_memory_eq(&memory, &[1105, 2, 0, 99]);
         assert_eq!(pointer, 0);
     }
 
@@ -372,7 +442,7 @@ mod opcode_jump_if_true {
         let mut pointer = 0;
         let mut memory = input_generator("1105,0,0,99");
         opcode_jump_if_true(&mut memory, &mut pointer);
-        assert_eq!(_memory_to_string(&memory), "1105,0,0,99");
+        assert_memory_eq(&memory, &[1105, 0, 0, 99]);
         assert_eq!(pointer, 3);
     }
 }
@@ -413,13 +483,14 @@ fn opcode_jump_if_false(memory: &mut [i32], pointer: &mut usize) {
 #[cfg(test)]
 mod opcode_jump_if_false {
     use super::*;
+    use crate::intcode::diff::assert_memory_eq;
 
     #[test]
     fn position_mode_true() {
         let mut pointer = 0;
         let mut memory = input_generator("6,2,1,99");
         opcode_jump_if_false(&mut memory, &mut pointer);
-        assert_eq!(_memory_to_string(&memory), "6,2,1,99");
+        assert_memory_eq(&memory, &[6, 2, 1, 99]);
         assert_eq!(pointer, 3);
     }
 
@@ -428,7 +499,7 @@ mod opcode_jump_if_false {
         let mut pointer = 0;
         let mut memory = input_generator("6,2,0,99");
         opcode_jump_if_false(&mut memory, &mut pointer);
-        assert_eq!(_memory_to_string(&memory), "6,2,0,99");
+        assert_memory_eq(&memory, &[6, 2, 0, 99]);
         assert_eq!(pointer, 6);
     }
 
@@ -437,7 +508,7 @@ mod opcode_jump_if_false {
         let mut pointer = 0;
         let mut memory = input_generator("1106,2,0,99");
         opcode_jump_if_false(&mut memory, &mut pointer);
-        assert_eq!(_memory_to_string(&memory), "1106,2,0,99");
+        assert_memory_eq(&memory, &[1106, 2, 0, 99]);
         assert_eq!(pointer, 3);
     }
 
@@ -446,7 +517,7 @@ mod opcode_jump_if_false {
         let mut pointer = 0;
         let mut memory = input_generator("1106,0,0,99");
         opcode_jump_if_false(&mut memory, &mut pointer);
-        assert_eq!(_memory_to_string(&memory), "1106,0,0,99");
+        assert_memory_eq(&memory, &[1106, 0, 0, 99]);
         assert_eq!(pointer, 0);
     }
 }
@@ -488,13 +559,14 @@ fn opcode_less_than(memory: &mut [i32], pointer: &mut usize) {
 #[cfg(test)]
 mod opcode_less_than {
     use super::*;
+    use crate::intcode::diff::assert_memory_eq;
 
     #[test]
     fn position_mode_true() {
         let mut pointer = 0;
         let mut memory = input_generator("7,0,4,0,99");
         opcode_less_than(&mut memory, &mut pointer);
-        assert_eq!(_memory_to_string(&memory), "1,0,4,0,99");
+        assert_memory_eq(&memory, &[1, 0, 4, 0, 99]);
         assert_eq!(pointer, 4);
     }
 
@@ -503,7 +575,7 @@ mod opcode_less_than {
         let mut pointer = 0;
         let mut memory = input_generator("7,0,2,0,99");
         opcode_less_than(&mut memory, &mut pointer);
-        assert_eq!(_memory_to_string(&memory), "0,0,2,0,99");
+        assert_memory_eq(&memory, &[0, 0, 2, 0, 99]);
         assert_eq!(pointer, 4);
     }
 
@@ -512,7 +584,7 @@ mod opcode_less_than {
         let mut pointer = 0;
         let mut memory = input_generator("1107,0,2,0,99");
         opcode_less_than(&mut memory, &mut pointer);
-        assert_eq!(_memory_to_string(&memory), "1,0,2,0,99");
+        assert_memory_eq(&memory, &[1, 0, 2, 0, 99]);
         assert_eq!(pointer, 4);
     }
 
@@ -521,7 +593,7 @@ mod opcode_less_than {
         let mut pointer = 0;
         let mut memory = input_generator("1107,2,0,0,99");
         opcode_less_than(&mut memory, &mut pointer);
-        assert_eq!(_memory_to_string(&memory), "0,2,0,0,99");
+        assert_memory_eq(&memory, &[0, 2, 0, 0, 99]);
         assert_eq!(pointer, 4);
     }
 }
@@ -563,13 +635,14 @@ fn opcode_equals(memory: &mut [i32], pointer: &mut usize) {
 #[cfg(test)]
 mod opcode_equals {
     use super::*;
+    use crate::intcode::diff::assert_memory_eq;
 
     #[test]
     fn position_mode_true() {
         let mut pointer = 0;
         let mut memory = input_generator("8,0,0,0,99");
         opcode_equals(&mut memory, &mut pointer);
-        assert_eq!(_memory_to_string(&memory), "1,0,0,0,99");
+        assert_memory_eq(&memory, &[1, 0, 0, 0, 99]);
         assert_eq!(pointer, 4);
     }
 
@@ -578,7 +651,7 @@ mod opcode_equals {
         let mut pointer = 0;
         let mut memory = input_generator("8,0,2,0,99");
         opcode_equals(&mut memory, &mut pointer);
-        assert_eq!(_memory_to_string(&memory), "0,0,2,0,99");
+        assert_memory_eq(&memory, &[0, 0, 2, 0, 99]);
         assert_eq!(pointer, 4);
     }
 
@@ -587,7 +660,7 @@ mod opcode_equals {
         let mut pointer = 0;
         let mut memory = input_generator("1108,2,2,0,99");
         opcode_equals(&mut memory, &mut pointer);
-        assert_eq!(_memory_to_string(&memory), "1,2,2,0,99");
+        assert_memory_eq(&memory, &[1, 2, 2, 0, 99]);
         assert_eq!(pointer, 4);
     }
 
@@ -596,7 +669,7 @@ mod opcode_equals {
         let mut pointer = 0;
         let mut memory = input_generator("1108,2,3,0,99");
         opcode_equals(&mut memory, &mut pointer);
-        assert_eq!(_memory_to_string(&memory), "0,2,3,0,99");
+        assert_memory_eq(&memory, &[0, 2, 3, 0, 99]);
         assert_eq!(pointer, 4);
     }
 }
@@ -621,14 +694,6 @@ fn get_parameter_mode(opcode: i32, position: i32) -> i32 {
     (opcode % large_offset - opcode % small_offset) / small_offset
 }
 
-fn _memory_to_string(memory: &[i32]) -> String {
-    memory
-        .iter()
-        .map(|&i| i.to_string())
-        .collect::<Vec<String>>()
-        .join(",")
-}
-
 #[cfg(test)]
 mod part2 {
     use super::*;
@@ -788,46 +853,90 @@ mod get_parameter_mode {
 #[cfg(test)]
 mod part1 {
     use super::*;
+    use crate::intcode::diff::assert_memory_eq;
 
     #[test]
     fn example_1() {
         let mut memory = input_generator("1,0,0,0,99");
         assert_eq!(run(&mut memory, 1), "");
-        assert_eq!(_memory_to_string(&memory), "2,0,0,0,99");
+        assert_memory_eq(&memory, &[2, 0, 0, 0, 99]);
     }
 
     #[test]
     fn example_2() {
         let mut memory = input_generator("2,3,0,3,99");
         assert_eq!(run(&mut memory, 1), "");
-        assert_eq!(_memory_to_string(&memory), "2,3,0,6,99");
+        assert_memory_eq(&memory, &[2, 3, 0, 6, 99]);
     }
 
     #[test]
     fn example_3() {
         let mut memory = input_generator("2,4,4,5,99,0");
         assert_eq!(run(&mut memory, 1), "");
-        assert_eq!(_memory_to_string(&memory), "2,4,4,5,99,9801");
+        assert_memory_eq(&memory, &[2, 4, 4, 5, 99, 9801]);
     }
 
     #[test]
     fn example_4() {
         let mut memory = input_generator("1,1,1,4,99,5,6,0,99");
         assert_eq!(run(&mut memory, 1), "");
-        assert_eq!(_memory_to_string(&memory), "30,1,1,4,2,5,6,0,99");
+        assert_memory_eq(&memory, &[30, 1, 1, 4, 2, 5, 6, 0, 99]);
     }
 
     #[test]
     fn example_5() {
         let mut memory = input_generator("1002,4,3,4,33");
         assert_eq!(run(&mut memory, 1), "");
-        assert_eq!(_memory_to_string(&memory), "1002,4,3,4,99");
+        assert_memory_eq(&memory, &[1002, 4, 3, 4, 99]);
     }
 
     #[test]
     fn example_input_output() {
         let mut memory = input_generator("3,0,4,0,99");
         assert_eq!(run(&mut memory, 1), "1");
-        assert_eq!(_memory_to_string(&memory), "1,0,4,0,99");
+        assert_memory_eq(&memory, &[1, 0, 4, 0, 99]);
+    }
+
+    #[test]
+    fn report() {
+        let mut memory = input_generator("3,0,4,0,99");
+        let (_, report) = run_with_report(&mut memory, 1);
+        assert_eq!(
+            report.to_string(),
+            "halted at address 4 after 3 steps with 1 output"
+        );
+
+        let mut memory = input_generator("1101,1,1,0");
+        let (_, report) = run_with_report(&mut memory, 1);
+        assert_eq!(report.reason, ExitReason::RanOffEnd);
+        assert_eq!(report.pointer, 4);
+    }
+
+    #[test]
+    fn report_fault() {
+        let mut memory = input_generator("3,0,4,0,42");
+        let (output, report) = run_with_report(&mut memory, 1);
+        assert_eq!(output, "1");
+        assert_eq!(
+            report.to_string(),
+            "faulted at address 4 after 2 steps with 1 output: unknown opcode 42 at address 4"
+        );
+
+        let mut memory = input_generator("1101,1,1,0,204,0,99");
+        let (_, report) = run_with_report(&mut memory, 1);
+        assert_eq!(
+            report.reason,
+            ExitReason::Fault(IntcodeError::UnknownMode {
+                address: 4,
+                position: 1,
+                mode: 2
+            })
+        );
+
+        // Only the first instruction is given input.
+        let mut memory = input_generator("3,0,3,0,99");
+        let (_, report) = run_with_report(&mut memory, 1);
+        assert_eq!(report.reason, ExitReason::NeedInput);
+        assert_eq!(report.pointer, 2);
     }
 }
@@ -0,0 +1,144 @@
+/*
+ * Differential fuzzing of the Intcode interpreters.
+ *
+ * Day 2, Day 5 and the shared machine each implement the add/multiply opcodes
+ * separately, so generate random add/multiply programs, run them through
+ * every interpreter and check that they all leave memory in the same state.
+ * The shared machine is run with both of its memory backends.
+ * The peephole optimizer only promises to preserve data, so its output is
+ * checked against the data region alone.
+ *
+ * Programs are laid out as a block of instructions, a halt, and then a data
+ * region. Writes only ever target the data region so the code can't rewrite
+ * itself into an invalid program, but operands may read from anywhere.
+ */
+
+use proptest::prelude::*;
+use proptest::sample::Index;
+
+use crate::day2;
+use crate::day5;
+use crate::intcode::memory::Backend;
+use crate::intcode::opcode::Level;
+use crate::intcode::optimizer::optimize;
+use crate::intcode::{Machine, Word};
+
+#[derive(Clone, Debug)]
+struct Instruction {
+    opcode: u32,
+    a: Index,
+    b: Index,
+    c: Index,
+}
+
+#[derive(Clone, Debug)]
+struct Program {
+    instructions: Vec<Instruction>,
+    data: Vec<u32>,
+}
+
+impl Program {
+    fn to_memory(&self) -> Vec<u32> {
+        let data_start = self.instructions.len() * 4 + 1;
+        let len = data_start + self.data.len();
+
+        let mut memory = Vec::with_capacity(len);
+        for instruction in &self.instructions {
+            memory.push(instruction.opcode);
+            memory.push(instruction.a.index(len) as u32);
+            memory.push(instruction.b.index(len) as u32);
+            memory.push((data_start + instruction.c.index(self.data.len())) as u32);
+        }
+        memory.push(99);
+        memory.extend_from_slice(&self.data);
+
+        memory
+    }
+}
+
+fn instruction() -> impl Strategy<Value = Instruction> {
+    (1..=2u32, any::<Index>(), any::<Index>(), any::<Index>())
+        .prop_map(|(opcode, a, b, c)| Instruction { opcode, a, b, c })
+}
+
+fn program() -> impl Strategy<Value = Program> {
+    (
+        prop::collection::vec(instruction(), 1..8),
+        prop::collection::vec(0..10u32, 1..8),
+    )
+        .prop_map(|(instructions, data)| Program { instructions, data })
+}
+
+// Reference model with checked arithmetic. Returns `None` if any value
+// leaves the range that both the `u32` (Day 2) and `i32` (Day 5) memories
+// can represent, as those programs can't be compared.
+fn reference_execute(memory: &[u32]) -> Option<Vec<i64>> {
+    let mut memory: Vec<i64> = memory.iter().map(|&i| i64::from(i)).collect();
+
+    let mut pointer = 0;
+    while memory[pointer] != 99 {
+        let a = memory[memory[pointer + 1] as usize];
+        let b = memory[memory[pointer + 2] as usize];
+        let c = memory[pointer + 3] as usize;
+
+        let value = match memory[pointer] {
+            1 => a + b,
+            2 => a * b,
+            opcode => panic!("generated an invalid opcode: {:?}", opcode),
+        };
+        if value > i64::from(i32::MAX) {
+            return None;
+        }
+
+        memory[c] = value;
+        pointer += 4;
+    }
+
+    Some(memory)
+}
+
+proptest! {
+    #[test]
+    fn add_multiply_final_memory(program in program()) {
+        let memory = program.to_memory();
+        let data_start = program.instructions.len() * 4 + 1;
+
+        let expected = reference_execute(&memory);
+        prop_assume!(expected.is_some());
+        let expected = expected.unwrap();
+
+        let mut day2_memory = memory.clone();
+        day2::execute(&mut day2_memory);
+        let day2_memory: Vec<i64> = day2_memory.iter().map(|&i| i64::from(i)).collect();
+
+        let mut day5_memory: Vec<i32> = memory.iter().map(|&i| i as i32).collect();
+        day5::run(&mut day5_memory, 0);
+        let day5_memory: Vec<i64> = day5_memory.iter().map(|&i| i64::from(i)).collect();
+
+        let program: Vec<Word> = memory.iter().map(|&i| Word::from(i)).collect();
+        // Run on a fork, which mustn't change the memory it was forked from.
+        let mut original = Machine::new(&program);
+        original.set_level(Level::Day2);
+        let mut machine = original.fork();
+        let outputs = machine.run_to_halt().unwrap();
+        prop_assert_eq!(original.memory(), &program[..]);
+
+        let mut sparse = original.fork();
+        sparse.set_backend(Backend::Sparse);
+        prop_assert_eq!(sparse.run_to_halt().unwrap(), outputs);
+
+        prop_assert_eq!(&day2_memory, &expected, "day2 disagrees on {:?}", memory);
+        prop_assert_eq!(&day5_memory, &expected, "day5 disagrees on {:?}", memory);
+        prop_assert_eq!(machine.memory(), &expected[..], "machine disagrees on {:?}", memory);
+        prop_assert_eq!(sparse.memory(), &expected[..], "sparse memory disagrees on {:?}", memory);
+
+        let mut optimized = Machine::new(&optimize(&program).program);
+        optimized.run_to_halt().unwrap();
+        prop_assert_eq!(
+            &optimized.memory().to_vec()[data_start..],
+            &expected[data_start..],
+            "optimized program disagrees on {:?}",
+            memory
+        );
+    }
+}
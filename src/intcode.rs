@@ -0,0 +1,1034 @@
+/*
+ * Shared Intcode machine.
+ *
+ * Implements the same instructions as the Day 5 interpreter, but keeps its
+ * state between calls so programs can be fed input and paused on output,
+ * and reports problems as errors instead of panicking. The instructions
+ * themselves are defined by the opcode registry.
+ *
+ * Memory grows as it is written, and reading past the end returns 0. It is
+ * copy-on-write, so machines can be forked cheaply, and programs that write to
+ * huge addresses can use the sparse memory backend; with the default dense
+ * backend they fault instead. Devices can be mapped over ranges of addresses,
+ * see `device`.
+ *
+ * Like Day 5, the mode of a write parameter is ignored, as are any mode digits
+ * past an instruction's last parameter. Strict mode rejects both, to help
+ * validate hand-written programs. Negative addresses are always rejected.
+ *
+ * The instruction set can be limited to an earlier day's, so programs run with
+ * that day's semantics and can be checked for relying on newer features: at
+ * `Level::Day2` only add, multiply and halt exist and any mode digit is an
+ * error.
+ */
+
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
+
+pub mod ascii;
+pub mod assembler;
+pub mod coverage;
+pub mod dap;
+pub mod device;
+pub mod diff;
+pub mod disassembler;
+pub mod dump;
+pub mod exit;
+pub mod explore;
+pub mod gdb;
+pub mod memory;
+pub mod opcode;
+pub mod optimizer;
+pub mod parser;
+pub mod pool;
+pub mod provenance;
+pub mod session;
+pub mod taint;
+pub mod trace;
+
+use self::coverage::Coverage;
+use self::device::Device;
+use self::exit::{ExitReason, ExitReport};
+use self::memory::{Backend, Memory};
+use self::opcode::{Control, Level, Registry};
+use self::session::Record;
+use self::taint::Taint;
+use self::trace::TraceEntry;
+
+pub type Word = i64;
+
+// Parse a program, allowing comments and a trailing comma, and panic with
+// the position of any invalid value.
+pub fn parse(input: &str) -> Vec<Word> {
+    parser::parse_with(input, &parser::Options::lenient()).unwrap_or_else(|e| panic!("{}", e))
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum IntcodeError {
+    UnknownOpcode {
+        address: usize,
+        opcode: Word,
+    },
+    UnknownMode {
+        address: usize,
+        position: usize,
+        mode: Word,
+    },
+    ImmediateWrite {
+        address: usize,
+        position: usize,
+    },
+    ExtraModes {
+        address: usize,
+        instruction: Word,
+    },
+    NegativeAddress {
+        address: usize,
+        value: Word,
+    },
+    MissingInput {
+        address: usize,
+    },
+    StepLimit {
+        steps: u64,
+    },
+    Overflow {
+        address: usize,
+    },
+    /// A write past the end of what dense memory allows.
+    AddressLimit {
+        address: usize,
+        value: Word,
+    },
+}
+
+impl fmt::Display for IntcodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IntcodeError::UnknownOpcode { address, opcode } => {
+                write!(f, "unknown opcode {} at address {}", opcode, address)
+            }
+            IntcodeError::UnknownMode {
+                address,
+                position,
+                mode,
+            } => write!(
+                f,
+                "unknown mode {} for parameter {} at address {}",
+                mode, position, address
+            ),
+            IntcodeError::ImmediateWrite { address, position } => write!(
+                f,
+                "immediate mode for write parameter {} at address {}",
+                position, address
+            ),
+            IntcodeError::ExtraModes {
+                address,
+                instruction,
+            } => write!(
+                f,
+                "instruction {} has modes for missing parameters at address {}",
+                instruction, address
+            ),
+            IntcodeError::NegativeAddress { address, value } => {
+                write!(f, "negative address {} at address {}", value, address)
+            }
+            IntcodeError::MissingInput { address } => {
+                write!(f, "missing input value at address {}", address)
+            }
+            IntcodeError::StepLimit { steps } => write!(f, "step limit of {} reached", steps),
+            IntcodeError::Overflow { address } => {
+                write!(f, "arithmetic overflow at address {}", address)
+            }
+            IntcodeError::AddressLimit { address, value } => write!(
+                f,
+                "address {} at address {} is past the dense memory limit",
+                value, address
+            ),
+        }
+    }
+}
+
+impl Error for IntcodeError {}
+
+/// Why the machine stopped running.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Event {
+    Output(Word),
+    NeedInput,
+    Halted,
+}
+
+#[derive(Clone, Debug)]
+pub struct Machine {
+    memory: Memory,
+    pointer: usize,
+    input: VecDeque<Word>,
+    halted: bool,
+    ran_off_end: bool,
+    steps: u64,
+    outputs: u64,
+    step_limit: Option<u64>,
+    strict: bool,
+    level: Level,
+    coverage: Option<Coverage>,
+    recording: Option<Vec<Record>>,
+    trace: Option<Vec<TraceEntry>>,
+    taint: Option<Taint>,
+    // The trace entry for the instruction being executed.
+    pending: Option<TraceEntry>,
+    // The most recently executed instructions, up to `history_len`.
+    history: VecDeque<TraceEntry>,
+    history_len: usize,
+    // Devices by the first address they're mapped to.
+    devices: Vec<(usize, Box<dyn Device>)>,
+    registry: Arc<Registry>,
+}
+
+impl Machine {
+    pub fn new(program: &[Word]) -> Machine {
+        Machine::with_registry(program, Registry::standard())
+    }
+
+    pub fn with_registry(program: &[Word], registry: Arc<Registry>) -> Machine {
+        Machine {
+            memory: Memory::new(program),
+            pointer: 0,
+            input: VecDeque::new(),
+            halted: false,
+            ran_off_end: false,
+            steps: 0,
+            outputs: 0,
+            step_limit: None,
+            strict: false,
+            level: Level::Full,
+            coverage: None,
+            recording: None,
+            trace: None,
+            taint: None,
+            pending: None,
+            history: VecDeque::new(),
+            history_len: 0,
+            devices: Vec::new(),
+            registry,
+        }
+    }
+
+    /// Reset to the state of `snapshot`, keeping this machine's own copies of
+    /// any memory pages to reuse.
+    pub fn restore(&mut self, snapshot: &Machine) {
+        let mut memory = std::mem::take(&mut self.memory);
+        memory.restore(&snapshot.memory);
+        *self = Machine {
+            memory,
+            ..snapshot.clone()
+        };
+    }
+
+    pub fn registry(&self) -> &Registry {
+        &self.registry
+    }
+
+    pub fn memory(&self) -> &Memory {
+        &self.memory
+    }
+
+    /// A copy of the machine that carries on independently. Memory is shared
+    /// until either of them writes to it, so forking takes the same time
+    /// however large the program is.
+    pub fn fork(&self) -> Machine {
+        self.clone()
+    }
+
+    pub fn pointer(&self) -> usize {
+        self.pointer
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    /// Whether the machine halted by running past the end of memory, rather
+    /// than with opcode 99.
+    pub fn ran_off_end(&self) -> bool {
+        self.ran_off_end
+    }
+
+    /// The number of instructions executed so far.
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// The number of values output so far.
+    pub fn outputs_produced(&self) -> u64 {
+        self.outputs
+    }
+
+    /// A report of the machine's current state, having stopped for `reason`.
+    pub fn report(&self, reason: ExitReason) -> ExitReport {
+        ExitReport {
+            reason,
+            pointer: self.pointer,
+            steps: self.steps,
+            outputs: self.outputs,
+        }
+    }
+
+    /// Fail with `StepLimit` instead of executing more than `limit`
+    /// instructions in total.
+    pub fn set_step_limit(&mut self, limit: Option<u64>) {
+        self.step_limit = limit;
+    }
+
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    /// Switch the memory to another backend, keeping its contents.
+    pub fn set_backend(&mut self, backend: Backend) {
+        self.memory = self.memory.to_backend(backend);
+    }
+
+    /// Reject opcodes and modes that aren't available at `level`.
+    pub fn set_level(&mut self, level: Level) {
+        self.level = level;
+    }
+
+    pub fn level(&self) -> Level {
+        self.level
+    }
+
+    /// Start recording coverage from now on, or stop and discard it.
+    pub fn set_coverage(&mut self, enabled: bool) {
+        self.coverage = if enabled { Some(Coverage::new()) } else { None };
+    }
+
+    pub fn coverage(&self) -> Option<&Coverage> {
+        self.coverage.as_ref()
+    }
+
+    /// Start recording input and output from now on, or stop and discard the
+    /// recording.
+    pub fn set_recording(&mut self, enabled: bool) {
+        self.recording = if enabled { Some(Vec::new()) } else { None };
+    }
+
+    pub fn recording(&self) -> Option<&[Record]> {
+        self.recording.as_deref()
+    }
+
+    /// Start tracing every executed instruction from now on, or stop and
+    /// discard the trace.
+    pub fn set_trace(&mut self, enabled: bool) {
+        self.trace = if enabled { Some(Vec::new()) } else { None };
+    }
+
+    pub fn trace(&self) -> Option<&[TraceEntry]> {
+        self.trace.as_deref()
+    }
+
+    /// Remove and return the trace so far, so long runs can be streamed.
+    pub fn take_trace(&mut self) -> Vec<TraceEntry> {
+        self.trace.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+    /// Start tracking which inputs flow into memory and outputs from now on,
+    /// or stop and discard it.
+    pub fn set_taint(&mut self, enabled: bool) {
+        self.taint = if enabled { Some(Taint::new()) } else { None };
+    }
+
+    pub fn taint(&self) -> Option<&Taint> {
+        self.taint.as_ref()
+    }
+
+    /// Map a device onto the addresses starting at `address`.
+    ///
+    /// Panics if it overlaps a device that's already mapped.
+    pub fn map_device(&mut self, address: usize, device: Box<dyn Device>) {
+        for (start, other) in &self.devices {
+            if address < start + other.size() && *start < address + device.size() {
+                panic!("device at {} overlaps device at {}", address, start);
+            }
+        }
+        self.devices.push((address, device));
+    }
+
+    /// The device of type `T` mapped at `address`, if any.
+    pub fn device<T: Device + 'static>(&self, address: usize) -> Option<&T> {
+        self.devices
+            .iter()
+            .find(|(start, _)| *start == address)
+            .and_then(|(_, device)| device.as_any().downcast_ref())
+    }
+
+    // The device mapped over an address, and the offset into it.
+    fn device_at(&mut self, address: usize) -> Option<(&mut Box<dyn Device>, usize)> {
+        self.devices
+            .iter_mut()
+            .find(|(start, device)| (*start..start + device.size()).contains(&address))
+            .map(|(start, device)| (device, address - *start))
+    }
+
+    /// Keep the last `len` executed instructions, for core dumps.
+    pub fn set_history(&mut self, len: usize) {
+        self.history_len = len;
+        while self.history.len() > len {
+            self.history.pop_front();
+        }
+    }
+
+    pub fn history(&self) -> impl Iterator<Item = &TraceEntry> {
+        self.history.iter()
+    }
+
+    /// The input queued but not yet read.
+    pub fn pending_input(&self) -> impl Iterator<Item = Word> + '_ {
+        self.input.iter().copied()
+    }
+
+    pub fn push_input(&mut self, value: Word) {
+        self.input.push_back(value);
+    }
+
+    pub fn extend_input<I: IntoIterator<Item = Word>>(&mut self, values: I) {
+        self.input.extend(values);
+    }
+
+    pub fn pop_input(&mut self) -> Option<Word> {
+        let value = self.input.pop_front()?;
+        if let Some(entry) = &mut self.pending {
+            entry.input = Some(value);
+        }
+        if let Some(recording) = &mut self.recording {
+            recording.push(Record::Input {
+                step: self.steps,
+                value,
+            });
+        }
+
+        Some(value)
+    }
+
+    /// Read memory, ignoring any device mapped over the address. Unlike
+    /// `read`, this has no side effects, so it's what debuggers use.
+    pub fn peek(&self, address: usize) -> Word {
+        self.memory.get(address)
+    }
+
+    /// Write memory, ignoring any device mapped over the address, and without
+    /// counting as a write for coverage or tracing. The counterpart of `peek`.
+    pub fn poke(&mut self, address: usize, value: Word) {
+        self.memory.set(address, value);
+    }
+
+    /// Read data, from a device if one is mapped over the address.
+    pub fn read(&mut self, address: usize) -> Word {
+        let value = match self.device_at(address) {
+            Some((device, offset)) => device.read(offset),
+            None => self.memory.get(address),
+        };
+
+        if let Some(coverage) = &mut self.coverage {
+            coverage.mark(address, coverage::READ);
+        }
+        if let Some(entry) = &mut self.pending {
+            entry.reads.push((address, value));
+        }
+        value
+    }
+
+    /// Write to memory, or to a device if one is mapped over the address.
+    pub fn write(&mut self, address: usize, value: Word) {
+        if let Some((device, offset)) = self.device_at(address) {
+            device.write(offset, value);
+        } else {
+            self.memory.set(address, value);
+        }
+
+        if let Some(coverage) = &mut self.coverage {
+            coverage.mark(address, coverage::WRITTEN);
+        }
+        if let Some(entry) = &mut self.pending {
+            entry.writes.push((address, value));
+        }
+    }
+
+    /// Run until the program outputs a value, needs input or halts.
+    pub fn run(&mut self) -> Result<Event, IntcodeError> {
+        loop {
+            if let Some(event) = self.step()? {
+                return Ok(event);
+            }
+        }
+    }
+
+    /// Run until the program halts, returning every value it output.
+    pub fn run_to_halt(&mut self) -> Result<Vec<Word>, IntcodeError> {
+        self.outputs().collect()
+    }
+
+    /// Run until the program halts or can't carry on, returning every value
+    /// it output and a report of why it stopped.
+    pub fn run_to_exit(&mut self) -> (Vec<Word>, ExitReport) {
+        let mut outputs = Vec::new();
+        let reason = loop {
+            match self.run() {
+                Ok(Event::Output(value)) => outputs.push(value),
+                Ok(Event::NeedInput) => break ExitReason::NeedInput,
+                Ok(Event::Halted) if self.ran_off_end() => break ExitReason::RanOffEnd,
+                Ok(Event::Halted) => break ExitReason::Halted,
+                Err(IntcodeError::StepLimit { .. }) => break ExitReason::StepLimit,
+                Err(error) => break ExitReason::Fault(error),
+            }
+        };
+
+        (outputs, self.report(reason))
+    }
+
+    /// Lazily run the program, yielding each value it outputs until it
+    /// halts. Needing input that isn't queued is a `MissingInput` error, and
+    /// the iterator ends after any error; push more input and call `outputs`
+    /// again to carry on.
+    pub fn outputs(&mut self) -> Outputs<'_> {
+        Outputs {
+            machine: self,
+            done: false,
+        }
+    }
+
+    /// Execute a single instruction.
+    ///
+    /// Returns `None` if the machine can keep running. An input instruction
+    /// without any input available doesn't execute, so it's retried once
+    /// input is pushed.
+    pub fn step(&mut self) -> Result<Option<Event>, IntcodeError> {
+        let result = self.execute();
+
+        // Only instructions that completed are traced.
+        if let (Some(entry), Ok(event)) = (self.pending.take(), &result) {
+            if *event != Some(Event::NeedInput) {
+                if let Some(taint) = &mut self.taint {
+                    taint.update(&self.registry, &entry);
+                }
+                if self.history_len > 0 {
+                    if self.history.len() == self.history_len {
+                        self.history.pop_front();
+                    }
+                    self.history.push_back(entry.clone());
+                }
+                if let Some(trace) = &mut self.trace {
+                    trace.push(entry);
+                }
+            }
+        }
+
+        result
+    }
+
+    fn execute(&mut self) -> Result<Option<Event>, IntcodeError> {
+        if self.halted {
+            return Ok(Some(Event::Halted));
+        }
+        if self.pointer >= self.memory.len() {
+            self.halted = true;
+            self.ran_off_end = true;
+            return Ok(Some(Event::Halted));
+        }
+
+        if let Some(limit) = self.step_limit {
+            if self.steps >= limit {
+                return Err(IntcodeError::StepLimit { steps: self.steps });
+            }
+        }
+
+        let registry = Arc::clone(&self.registry);
+        let instruction = self.peek(self.pointer);
+        let opcode = match registry.get(instruction % 100) {
+            Some(opcode) if self.level.has_opcode(opcode.number) => opcode,
+            _ => {
+                return Err(IntcodeError::UnknownOpcode {
+                    address: self.pointer,
+                    opcode: instruction % 100,
+                })
+            }
+        };
+
+        if !self.level.has_modes() && instruction / 100 != 0 {
+            let position = (1..=opcode.parameters).find(|&p| opcode::mode(instruction, p) != 0);
+            return Err(match position {
+                Some(position) => {
+                    self.mode_error(position, opcode::mode(instruction, position), false)
+                }
+                None => IntcodeError::ExtraModes {
+                    address: self.pointer,
+                    instruction,
+                },
+            });
+        }
+
+        if self.strict && instruction / 10i64.pow(opcode.parameters as u32 + 2) != 0 {
+            return Err(IntcodeError::ExtraModes {
+                address: self.pointer,
+                instruction,
+            });
+        }
+
+        if let Some(coverage) = &mut self.coverage {
+            coverage.mark(self.pointer, coverage::EXECUTED);
+            for position in 1..=opcode.parameters {
+                coverage.mark(self.pointer + position, coverage::PARAMETER);
+            }
+        }
+
+        if self.trace.is_some() || self.taint.is_some() || self.history_len > 0 {
+            self.pending = Some(TraceEntry {
+                step: self.steps,
+                address: self.pointer,
+                words: (0..opcode.size())
+                    .map(|offset| self.peek(self.pointer + offset))
+                    .collect(),
+                ..Default::default()
+            });
+        }
+
+        let mut parameters = Vec::with_capacity(opcode.parameters);
+        for position in 1..=opcode.parameters {
+            let value = self.peek(self.pointer + position);
+            let mode = opcode::mode(instruction, position);
+
+            if opcode.writes_to(position) {
+                if self.strict && mode != 0 {
+                    return Err(self.mode_error(position, mode, true));
+                }
+
+                // Write parameters are always addresses, whatever their mode.
+                let address = self.address(value)?;
+                if !self.memory.fits(address) && self.device_at(address).is_none() {
+                    return Err(IntcodeError::AddressLimit {
+                        address: self.pointer,
+                        value,
+                    });
+                }
+                parameters.push(address as Word);
+                continue;
+            }
+
+            match mode {
+                0 => {
+                    let address = self.address(value)?;
+                    let value = self.read(address);
+                    parameters.push(value);
+                }
+                1 => parameters.push(value),
+                _ => return Err(self.mode_error(position, mode, false)),
+            }
+        }
+
+        let control = (opcode.execute)(self, &parameters)?;
+        if control == Control::Wait {
+            return Ok(Some(Event::NeedInput));
+        }
+
+        let step = self.steps;
+        self.steps += 1;
+        for (_, device) in &mut self.devices {
+            device.tick();
+        }
+
+        let event = match control {
+            Control::Next => {
+                self.pointer += opcode.size();
+                None
+            }
+            Control::Jump(target) => {
+                self.pointer = self.address(target)?;
+                None
+            }
+            Control::Output(value) => {
+                if let Some(recording) = &mut self.recording {
+                    recording.push(Record::Output { step, value });
+                }
+                if let Some(entry) = &mut self.pending {
+                    entry.output = Some(value);
+                }
+                self.outputs += 1;
+                self.pointer += opcode.size();
+                Some(Event::Output(value))
+            }
+            Control::Wait => unreachable!(),
+            Control::Halt => {
+                self.halted = true;
+                Some(Event::Halted)
+            }
+        };
+
+        if let Some(entry) = &mut self.pending {
+            entry.next = if self.halted {
+                self.pointer + opcode.size()
+            } else {
+                self.pointer
+            };
+        }
+
+        Ok(event)
+    }
+
+    fn mode_error(&self, position: usize, mode: Word, write: bool) -> IntcodeError {
+        if write && mode == 1 {
+            IntcodeError::ImmediateWrite {
+                address: self.pointer,
+                position,
+            }
+        } else {
+            IntcodeError::UnknownMode {
+                address: self.pointer,
+                position,
+                mode,
+            }
+        }
+    }
+
+    // Convert a value into an address, which must not be negative.
+    fn address(&self, value: Word) -> Result<usize, IntcodeError> {
+        if value < 0 {
+            Err(IntcodeError::NegativeAddress {
+                address: self.pointer,
+                value,
+            })
+        } else {
+            Ok(value as usize)
+        }
+    }
+}
+
+/// The iterator returned by `Machine::outputs`.
+#[derive(Debug)]
+pub struct Outputs<'a> {
+    machine: &'a mut Machine,
+    done: bool,
+}
+
+impl Iterator for Outputs<'_> {
+    type Item = Result<Word, IntcodeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let result = match self.machine.run() {
+            Ok(Event::Output(value)) => return Some(Ok(value)),
+            Ok(Event::Halted) => None,
+            Ok(Event::NeedInput) => Some(Err(IntcodeError::MissingInput {
+                address: self.machine.pointer,
+            })),
+            Err(error) => Some(Err(error)),
+        };
+        self.done = true;
+        result
+    }
+}
+
+impl std::iter::FusedIterator for Outputs<'_> {}
+
+#[cfg(test)]
+mod machine {
+    use super::*;
+
+    #[test]
+    fn add_multiply() {
+        let mut machine = Machine::new(&parse("1,9,10,3,2,3,11,0,99,30,40,50"));
+        assert_eq!(machine.run_to_halt(), Ok(vec![]));
+        assert_eq!(
+            machine.memory(),
+            &parse("3500,9,10,70,2,3,11,0,99,30,40,50")[..]
+        );
+    }
+
+    #[test]
+    fn input_output() {
+        let mut machine = Machine::new(&parse("3,0,4,0,99"));
+        assert_eq!(machine.run(), Ok(Event::NeedInput));
+        machine.push_input(42);
+        assert_eq!(machine.run(), Ok(Event::Output(42)));
+        assert_eq!(machine.run(), Ok(Event::Halted));
+        assert!(machine.is_halted());
+    }
+
+    #[test]
+    fn compare_and_jump() {
+        let program = parse("3,21,1008,21,8,20,1005,20,22,107,8,21,20,1006,20,31,1106,0,36,98,0,0,1002,21,125,20,4,20,1105,1,46,104,999,1105,1,46,1101,1000,1,20,4,20,1105,1,46,98,99");
+        for &(input, output) in &[(7, 999), (8, 1000), (9, 1001)] {
+            let mut machine = Machine::new(&program);
+            machine.push_input(input);
+            assert_eq!(machine.run_to_halt(), Ok(vec![output]));
+        }
+    }
+
+    #[test]
+    fn write_past_end() {
+        let mut machine = Machine::new(&parse("1101,2,3,7,99"));
+        assert_eq!(machine.run_to_halt(), Ok(vec![]));
+        assert_eq!(machine.memory(), &parse("1101,2,3,7,99,0,0,5")[..]);
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            Machine::new(&parse("1,0,0,0,42")).run_to_halt(),
+            Err(IntcodeError::UnknownOpcode {
+                address: 4,
+                opcode: 42
+            })
+        );
+        assert_eq!(
+            Machine::new(&parse("201,0,0,0,99")).run_to_halt(),
+            Err(IntcodeError::UnknownMode {
+                address: 0,
+                position: 1,
+                mode: 2
+            })
+        );
+        assert_eq!(
+            Machine::new(&parse("1,-1,0,0,99")).run_to_halt(),
+            Err(IntcodeError::NegativeAddress {
+                address: 0,
+                value: -1
+            })
+        );
+        assert_eq!(
+            Machine::new(&parse("3,0,99")).run_to_halt(),
+            Err(IntcodeError::MissingInput { address: 0 })
+        );
+    }
+
+    #[test]
+    fn address_limit() {
+        // Faults on dense memory rather than allocating a huge page table.
+        let program = parse("1,0,0,9223372036854775807,99");
+        assert_eq!(
+            Machine::new(&program).run_to_halt(),
+            Err(IntcodeError::AddressLimit {
+                address: 0,
+                value: 9223372036854775807
+            })
+        );
+
+        let mut machine = Machine::new(&program);
+        machine.set_backend(Backend::Sparse);
+        assert_eq!(machine.run_to_halt(), Ok(vec![]));
+        assert_eq!(machine.peek(9223372036854775807), 2);
+    }
+
+    #[test]
+    fn overflow() {
+        assert_eq!(
+            Machine::new(&parse("104,0,1101,9223372036854775807,1,0,99")).run_to_halt(),
+            Err(IntcodeError::Overflow { address: 2 })
+        );
+        assert_eq!(
+            Machine::new(&parse("1102,9223372036854775807,2,0,99")).run_to_halt(),
+            Err(IntcodeError::Overflow { address: 0 })
+        );
+        assert_eq!(
+            Machine::new(&parse("1102,-9223372036854775808,-1,0,99")).run_to_halt(),
+            Err(IntcodeError::Overflow { address: 0 })
+        );
+    }
+
+    #[test]
+    fn lenient_modes() {
+        // Immediate mode input, and modes past the last parameter.
+        let mut machine = Machine::new(&parse("103,5,10004,5,99,0"));
+        machine.push_input(7);
+        assert_eq!(machine.run_to_halt(), Ok(vec![7]));
+    }
+
+    #[test]
+    fn strict() {
+        let strict = |program| {
+            let mut machine = Machine::new(&parse(program));
+            machine.set_strict(true);
+            machine.push_input(7);
+            machine.run_to_halt()
+        };
+
+        assert_eq!(strict("3,5,4,5,99,0"), Ok(vec![7]));
+        assert_eq!(
+            strict("103,5,4,5,99,0"),
+            Err(IntcodeError::ImmediateWrite {
+                address: 0,
+                position: 1
+            })
+        );
+        assert_eq!(
+            strict("1,0,0,0,10099"),
+            Err(IntcodeError::ExtraModes {
+                address: 4,
+                instruction: 10099
+            })
+        );
+        assert_eq!(
+            strict("20001,0,0,0,99"),
+            Err(IntcodeError::UnknownMode {
+                address: 0,
+                position: 3,
+                mode: 2
+            })
+        );
+        assert_eq!(
+            strict("1105,1,-1"),
+            Err(IntcodeError::NegativeAddress {
+                address: 0,
+                value: -1
+            })
+        );
+    }
+
+    #[test]
+    fn fork() {
+        // Fork while waiting for input, and give each fork a different input
+        // to compare with 8.
+        let program = parse("3,9,8,9,10,9,4,9,99,-1,8");
+        let mut machine = Machine::new(&program);
+        assert_eq!(machine.run(), Ok(Event::NeedInput));
+
+        for &(input, output) in &[(7, 0), (8, 1), (9, 0)] {
+            let mut fork = machine.fork();
+            fork.push_input(input);
+            assert_eq!(fork.run_to_halt(), Ok(vec![output]));
+            assert_eq!(fork.read(9), output);
+        }
+        assert_eq!(machine.memory(), &program[..]);
+        assert_eq!(machine.run(), Ok(Event::NeedInput));
+    }
+
+    #[test]
+    fn outputs() {
+        // Outputs 1, 2, 3, ... forever.
+        let mut machine = Machine::new(&parse("101,1,7,7,4,7,1105,0,0"));
+        let first: Vec<Word> = machine.outputs().take(3).map(Result::unwrap).collect();
+        assert_eq!(first, vec![1, 2, 3]);
+        // Picks up where the last iterator left off.
+        assert_eq!(machine.outputs().next(), Some(Ok(4)));
+
+        // Pairs of outputs, with input requested in between.
+        let mut machine = Machine::new(&parse("104,1,104,2,3,0,104,3,104,4,99"));
+        let mut outputs = machine.outputs();
+        let pair = (outputs.next(), outputs.next());
+        assert_eq!(pair, (Some(Ok(1)), Some(Ok(2))));
+        assert_eq!(
+            outputs.next(),
+            Some(Err(IntcodeError::MissingInput { address: 4 }))
+        );
+        assert_eq!(outputs.next(), None);
+
+        machine.push_input(0);
+        let rest: Result<Vec<Word>, IntcodeError> = machine.outputs().collect();
+        assert_eq!(rest, Ok(vec![3, 4]));
+        assert_eq!(machine.outputs().next(), None);
+    }
+
+    #[test]
+    fn step_limit() {
+        // Loops forever.
+        let mut machine = Machine::new(&parse("1105,1,0"));
+        machine.set_step_limit(Some(10));
+        assert_eq!(
+            machine.run_to_halt(),
+            Err(IntcodeError::StepLimit { steps: 10 })
+        );
+        assert_eq!(machine.steps(), 10);
+
+        let mut machine = Machine::new(&parse("1,0,0,0,99"));
+        machine.set_step_limit(Some(2));
+        assert_eq!(machine.run_to_halt(), Ok(vec![]));
+    }
+
+    #[test]
+    fn levels() {
+        let run = |program: &str, level: Level| {
+            let mut machine = Machine::new(&parse(program));
+            machine.set_level(level);
+            machine.push_input(8);
+            machine.run_to_halt()
+        };
+
+        // Day 2 only has add, multiply and halt, without modes.
+        assert_eq!(run("1,0,0,0,2,0,0,0,99", Level::Day2), Ok(vec![]));
+        assert_eq!(
+            run("3,0,4,0,99", Level::Day2),
+            Err(IntcodeError::UnknownOpcode {
+                address: 0,
+                opcode: 3
+            })
+        );
+        assert_eq!(
+            run("1002,4,3,4,33", Level::Day2),
+            Err(IntcodeError::UnknownMode {
+                address: 0,
+                position: 2,
+                mode: 1
+            })
+        );
+        assert_eq!(
+            run("100001,0,0,0,99", Level::Day2),
+            Err(IntcodeError::ExtraModes {
+                address: 0,
+                instruction: 100001
+            })
+        );
+
+        // Day 5 adds the rest of the standard opcodes and modes.
+        assert_eq!(run("3,9,8,9,10,9,4,9,99,-1,8", Level::Day5), Ok(vec![1]));
+        assert_eq!(run("1002,4,3,4,33", Level::Day5), Ok(vec![]));
+    }
+
+    #[test]
+    fn levels_and_registry() {
+        // Only the full level allows opcodes registered on top of Day 5.
+        let mut registry = Registry::standard().as_ref().clone();
+        let mut opcode = registry.get(4).unwrap().clone();
+        opcode.number = 9;
+        opcode.mnemonic = "out2";
+        registry.register(opcode);
+
+        let mut machine = Machine::with_registry(&parse("9,0,99"), Arc::new(registry));
+        let mut day5 = machine.fork();
+        day5.set_level(Level::Day5);
+        assert_eq!(
+            day5.run_to_halt(),
+            Err(IntcodeError::UnknownOpcode {
+                address: 0,
+                opcode: 9
+            })
+        );
+        assert_eq!(machine.level(), Level::Full);
+        assert_eq!(machine.run_to_halt(), Ok(vec![9]));
+    }
+
+    #[test]
+    fn sparse_memory() {
+        // Stores 5 at 10^12 and outputs it, then reads untouched memory.
+        let program = parse("1101,5,0,1000000000000,4,1000000000000,4,999999999999,99");
+        let mut machine = Machine::new(&program);
+        machine.set_backend(Backend::Sparse);
+        let mut fork = machine.fork();
+        assert_eq!(fork.run_to_halt(), Ok(vec![5, 0]));
+        assert_eq!(fork.memory().len(), 1_000_000_000_001);
+
+        // Behaves the same as dense memory on ordinary programs.
+        let program = parse(include_str!("../input/2019/day5.txt"));
+        let mut dense = Machine::new(&program);
+        let mut sparse = Machine::new(&program);
+        sparse.set_backend(Backend::Sparse);
+        for machine in [&mut dense, &mut sparse] {
+            machine.push_input(5);
+        }
+        assert_eq!(dense.run_to_halt(), sparse.run_to_halt());
+        assert_eq!(dense.memory(), sparse.memory());
+    }
+}
@@ -0,0 +1,239 @@
+/*
+ * ASCII text I/O for Intcode programs.
+ *
+ * Lines of text are sent as their character codes followed by a newline (10),
+ * and output codes are collected into lines of text. Values that aren't ASCII
+ * (negative or above 127) are passed through as numbers.
+ */
+
+use std::error::Error;
+use std::fmt;
+use std::io::{BufRead, Write};
+
+use super::{Event, IntcodeError, Machine, Word};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Output {
+    Line(String),
+    Value(Word),
+}
+
+/// A line that can't be sent, because it has a character outside ASCII.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NonAscii {
+    pub character: char,
+}
+
+impl fmt::Display for NonAscii {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "can't send non-ASCII character {:?}", self.character)
+    }
+}
+
+impl Error for NonAscii {}
+
+#[derive(Clone, Debug)]
+pub struct Ascii {
+    machine: Machine,
+    line: String,
+    // Output decoded by a run that faulted, until it's taken.
+    output: Vec<Output>,
+}
+
+impl Ascii {
+    pub fn new(machine: Machine) -> Ascii {
+        Ascii {
+            machine,
+            line: String::new(),
+            output: Vec::new(),
+        }
+    }
+
+    pub fn machine(&self) -> &Machine {
+        &self.machine
+    }
+
+    pub fn into_inner(self) -> Machine {
+        self.machine
+    }
+
+    pub fn send_line(&mut self, line: &str) -> Result<(), NonAscii> {
+        if let Some(character) = line.chars().find(|c| !c.is_ascii()) {
+            return Err(NonAscii { character });
+        }
+
+        self.machine
+            .extend_input(line.bytes().chain(Some(b'\n')).map(Word::from));
+        Ok(())
+    }
+
+    /// Run until the program needs input or halts, returning its output.
+    ///
+    /// A line without a trailing newline, like a prompt, is returned when the
+    /// program stops or outputs a non-ASCII value. If the program faults, the
+    /// output before the fault can be had from `take_output`.
+    pub fn run(&mut self) -> Result<Vec<Output>, IntcodeError> {
+        loop {
+            let event = match self.machine.run() {
+                Ok(event) => event,
+                Err(error) => {
+                    self.flush();
+                    return Err(error);
+                }
+            };
+            match event {
+                Event::Output(10) => {
+                    let line = self.line.split_off(0);
+                    self.output.push(Output::Line(line));
+                }
+                Event::Output(value) if (0..=127).contains(&value) => {
+                    self.line.push(value as u8 as char)
+                }
+                Event::Output(value) => {
+                    self.flush();
+                    self.output.push(Output::Value(value));
+                }
+                Event::NeedInput | Event::Halted => {
+                    self.flush();
+                    return Ok(self.take_output());
+                }
+            }
+        }
+    }
+
+    /// The output of a run that faulted, up to the fault.
+    pub fn take_output(&mut self) -> Vec<Output> {
+        std::mem::take(&mut self.output)
+    }
+
+    /// Run the program as a line-based terminal session, sending each line
+    /// read from `input` whenever the program asks for it.
+    pub fn interact<R: BufRead, W: Write>(
+        &mut self,
+        mut input: R,
+        mut output: W,
+    ) -> Result<(), Box<dyn Error>> {
+        loop {
+            let (items, fault) = match self.run() {
+                Ok(items) => (items, None),
+                Err(error) => (self.take_output(), Some(error)),
+            };
+            for item in items {
+                match item {
+                    Output::Line(line) => writeln!(output, "{}", line)?,
+                    Output::Value(value) => writeln!(output, "{}", value)?,
+                }
+            }
+            output.flush()?;
+            if let Some(error) = fault {
+                return Err(error.into());
+            }
+
+            if self.machine.is_halted() {
+                return Ok(());
+            }
+
+            let mut line = String::new();
+            if input.read_line(&mut line)? == 0 {
+                return Err(IntcodeError::MissingInput {
+                    address: self.machine.pointer(),
+                }
+                .into());
+            }
+            self.send_line(line.trim_end_matches(&['\r', '\n'][..]))?;
+        }
+    }
+
+    fn flush(&mut self) {
+        if !self.line.is_empty() {
+            let line = self.line.split_off(0);
+            self.output.push(Output::Line(line));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_ascii {
+    use super::super::parse;
+    use super::*;
+
+    // Read a 3 character line, echo it back and then output 1000.
+    const ECHO_3: &str = "3,100,3,101,3,102,3,103,4,100,4,101,4,102,4,103,104,1000,99";
+
+    #[test]
+    fn send_line() {
+        let mut machine = Machine::new(&parse("3,10,3,11,3,12,99"));
+        let mut ascii = Ascii::new(machine.clone());
+        ascii.send_line("hi").unwrap();
+        assert_eq!(ascii.run(), Ok(vec![]));
+
+        machine = ascii.into_inner();
+        assert_eq!(&machine.memory().to_vec()[10..], &[104, 105, 10][..]);
+    }
+
+    #[test]
+    fn send_non_ascii() {
+        let mut ascii = Ascii::new(Machine::new(&parse("99")));
+        assert_eq!(ascii.send_line("héllo"), Err(NonAscii { character: 'é' }));
+    }
+
+    #[test]
+    fn lines_and_values() {
+        let mut ascii = Ascii::new(Machine::new(&parse(ECHO_3)));
+        assert_eq!(ascii.run(), Ok(vec![]));
+        ascii.send_line("abc").unwrap();
+        assert_eq!(
+            ascii.run(),
+            Ok(vec![Output::Line("abc".to_string()), Output::Value(1000)])
+        );
+    }
+
+    #[test]
+    fn prompt_without_newline() {
+        // Output "?" then wait for input.
+        let mut ascii = Ascii::new(Machine::new(&parse("104,63,3,0,99")));
+        assert_eq!(ascii.run(), Ok(vec![Output::Line("?".to_string())]));
+        assert!(!ascii.machine().is_halted());
+    }
+
+    #[test]
+    fn interact() {
+        let mut ascii = Ascii::new(Machine::new(&parse(ECHO_3)));
+        let mut output = Vec::new();
+        ascii.interact(&b"xyz\n"[..], &mut output).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "xyz\n1000\n");
+    }
+
+    #[test]
+    fn interact_out_of_input() {
+        let mut ascii = Ascii::new(Machine::new(&parse(ECHO_3)));
+        let mut output = Vec::new();
+        assert!(ascii.interact(&b"x"[..], &mut output).is_err());
+    }
+
+    #[test]
+    fn fault() {
+        // Output "ok\n" and "!", then hit opcode 42.
+        let mut ascii = Ascii::new(Machine::new(&parse("104,111,104,107,104,10,104,33,42")));
+        assert_eq!(
+            ascii.run(),
+            Err(IntcodeError::UnknownOpcode {
+                opcode: 42,
+                address: 8
+            })
+        );
+        assert_eq!(
+            ascii.take_output(),
+            vec![
+                Output::Line("ok".to_string()),
+                Output::Line("!".to_string())
+            ]
+        );
+        assert_eq!(ascii.take_output(), vec![]);
+
+        let mut ascii = Ascii::new(Machine::new(&parse("104,111,104,107,104,10,104,33,42")));
+        let mut output = Vec::new();
+        assert!(ascii.interact(&b""[..], &mut output).is_err());
+        assert_eq!(String::from_utf8(output).unwrap(), "ok\n!\n");
+    }
+}
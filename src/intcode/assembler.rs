@@ -0,0 +1,289 @@
+/*
+ * Intcode assembler.
+ *
+ * Assembles the disassembler's syntax, plus labels, using the mnemonics from
+ * the opcode registry:
+ *
+ *   # Output 1 if the input is 8, otherwise 0.
+ *   start: in [value]
+ *          eq [value], 8, [value]
+ *          out [value]
+ *          hlt
+ *   value: data 0
+ *
+ * Operands are either immediate values (`8`, `start`) or positions (`[9]`,
+ * `[value]`), where a label stands for the address it was defined at. A line
+ * may also start with a numeric address like `12:`, as in a disassembly
+ * listing, which must match the address the line assembles to.
+ *
+ * `assemble_with_map` also returns where each instruction and label came
+ * from, for debuggers to show the source line being executed.
+ */
+
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fmt;
+
+use super::opcode::{Opcode, Registry};
+use super::Word;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AssembleError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AssembleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for AssembleError {}
+
+enum Statement<'a> {
+    Instruction(&'a Opcode, Vec<&'a str>),
+    Data(Vec<&'a str>),
+}
+
+struct Line<'a> {
+    number: usize,
+    address: usize,
+    statement: Statement<'a>,
+}
+
+/// Where a program's instructions and labels are in its source.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SourceMap {
+    /// The address and 1-based line of every instruction, in address order.
+    pub instructions: Vec<(usize, usize)>,
+    pub labels: BTreeMap<String, usize>,
+}
+
+impl SourceMap {
+    /// The line of the instruction starting at `address`.
+    pub fn line(&self, address: usize) -> Option<usize> {
+        self.instructions
+            .binary_search_by_key(&address, |&(address, _)| address)
+            .ok()
+            .map(|index| self.instructions[index].1)
+    }
+
+    /// The address of the instruction on `line`.
+    pub fn address(&self, line: usize) -> Option<usize> {
+        self.instructions
+            .iter()
+            .find(|&&(_, l)| l == line)
+            .map(|&(address, _)| address)
+    }
+
+    /// The first label defined at `address`.
+    pub fn label(&self, address: usize) -> Option<&str> {
+        self.labels
+            .iter()
+            .find(|&(_, &a)| a == address)
+            .map(|(label, _)| label.as_str())
+    }
+}
+
+pub fn assemble(registry: &Registry, source: &str) -> Result<Vec<Word>, AssembleError> {
+    assemble_with_map(registry, source).map(|(program, _)| program)
+}
+
+pub fn assemble_with_map(
+    registry: &Registry,
+    source: &str,
+) -> Result<(Vec<Word>, SourceMap), AssembleError> {
+    // First pass: work out the address of every line and label.
+    let mut lines = Vec::new();
+    let mut labels = HashMap::new();
+    let mut address = 0;
+
+    for (index, text) in source.lines().enumerate() {
+        let number = index + 1;
+        let error = |message: String| AssembleError {
+            line: number,
+            message,
+        };
+
+        let mut text = text.split('#').next().unwrap().trim();
+        while let Some((label, rest)) = split_label(text) {
+            if let Ok(expected) = label.parse::<usize>() {
+                if expected != address {
+                    return Err(error(format!(
+                        "address {} doesn't match assembled address {}",
+                        expected, address
+                    )));
+                }
+            } else if labels.insert(label, address).is_some() {
+                return Err(error(format!("duplicate label: {:?}", label)));
+            }
+            text = rest;
+        }
+
+        if text.is_empty() {
+            continue;
+        }
+
+        let (mnemonic, operands) = match text.find(char::is_whitespace) {
+            Some(split) => (&text[..split], text[split..].trim()),
+            None => (text, ""),
+        };
+        let operands: Vec<&str> = if operands.is_empty() {
+            Vec::new()
+        } else {
+            operands.split(',').map(str::trim).collect()
+        };
+
+        let start = address;
+        let statement = if mnemonic == "data" {
+            if operands.is_empty() {
+                return Err(error("data needs at least one value".to_string()));
+            }
+            address += operands.len();
+            Statement::Data(operands)
+        } else {
+            let opcode = registry
+                .by_mnemonic(mnemonic)
+                .ok_or_else(|| error(format!("unknown mnemonic: {:?}", mnemonic)))?;
+            if operands.len() != opcode.parameters {
+                return Err(error(format!(
+                    "{} takes {} parameters, found {}",
+                    opcode.mnemonic,
+                    opcode.parameters,
+                    operands.len()
+                )));
+            }
+            address += opcode.size();
+            Statement::Instruction(opcode, operands)
+        };
+
+        lines.push(Line {
+            number,
+            address: start,
+            statement,
+        });
+    }
+
+    // Second pass: encode each line now that every label is known.
+    let mut program = Vec::with_capacity(address);
+    let mut map = SourceMap {
+        instructions: Vec::new(),
+        labels: labels
+            .iter()
+            .map(|(&label, &address)| (label.to_string(), address))
+            .collect(),
+    };
+    for line in &lines {
+        debug_assert_eq!(program.len(), line.address);
+
+        let error = |message: String| AssembleError {
+            line: line.number,
+            message,
+        };
+        let value = |operand: &str| {
+            resolve(operand, &labels).ok_or_else(|| error(format!("invalid value: {:?}", operand)))
+        };
+
+        match &line.statement {
+            Statement::Data(values) => {
+                for operand in values {
+                    program.push(value(operand)?);
+                }
+            }
+            Statement::Instruction(opcode, operands) => {
+                map.instructions.push((line.address, line.number));
+                let mut instruction = opcode.number;
+                let mut parameters = Vec::with_capacity(operands.len());
+                for (index, operand) in operands.iter().enumerate() {
+                    match operand.strip_prefix('[').and_then(|o| o.strip_suffix(']')) {
+                        Some(inner) => parameters.push(value(inner.trim())?),
+                        None => {
+                            instruction += 10i64.pow(index as u32 + 2);
+                            parameters.push(value(operand)?);
+                        }
+                    }
+                }
+                program.push(instruction);
+                program.extend(parameters);
+            }
+        }
+    }
+
+    Ok((program, map))
+}
+
+// Split a leading `label:` off a line.
+fn split_label(text: &str) -> Option<(&str, &str)> {
+    let colon = text.find(':')?;
+    let label = &text[..colon];
+    let valid = !label.is_empty()
+        && (label.chars().all(|c| c.is_ascii_digit())
+            || (label.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')));
+
+    if valid {
+        Some((label, text[colon + 1..].trim()))
+    } else {
+        None
+    }
+}
+
+fn resolve(operand: &str, labels: &HashMap<&str, usize>) -> Option<Word> {
+    operand
+        .parse()
+        .ok()
+        .or_else(|| labels.get(operand).map(|&address| address as Word))
+}
+
+#[cfg(test)]
+mod test_assembler {
+    use super::super::disassembler::disassemble;
+    use super::super::{parse, Machine};
+    use super::*;
+
+    #[test]
+    fn labels() {
+        let source = "
+            # Output 1 if the input is 8, otherwise 0.
+            start: in [value]
+                   eq [value], 8, [value]
+                   out [value]
+                   hlt
+            value: data 0
+        ";
+        let (program, map) = assemble_with_map(&Registry::standard(), source).unwrap();
+        assert_eq!(program, parse("3,9,1008,9,8,9,4,9,99,0"));
+        assert_eq!(map.instructions, vec![(0, 3), (2, 4), (6, 5), (8, 6)]);
+        assert_eq!((map.line(2), map.line(3)), (Some(4), None));
+        assert_eq!((map.address(6), map.address(7)), (Some(8), None));
+        assert_eq!((map.label(0), map.label(9)), (Some("start"), Some("value")));
+
+        let mut machine = Machine::new(&program);
+        machine.push_input(8);
+        assert_eq!(machine.run_to_halt(), Ok(vec![1]));
+    }
+
+    #[test]
+    fn round_trip() {
+        let registry = Registry::standard();
+        let program = parse(include_str!("../../input/2019/day5.txt"));
+        let listing = disassemble(&registry, &program);
+        assert_eq!(assemble(&registry, &listing), Ok(program));
+    }
+
+    #[test]
+    fn errors() {
+        let registry = Registry::standard();
+        let error = |source| assemble(&registry, source).unwrap_err().to_string();
+
+        assert_eq!(error("hlt\nnop"), "line 2: unknown mnemonic: \"nop\"");
+        assert_eq!(error("add 1, 2"), "line 1: add takes 3 parameters, found 2");
+        assert_eq!(error("out [nowhere]"), "line 1: invalid value: \"nowhere\"");
+        assert_eq!(error("a: hlt\na: hlt"), "line 2: duplicate label: \"a\"");
+        assert_eq!(
+            error("hlt\n3: hlt"),
+            "line 2: address 3 doesn't match assembled address 1"
+        );
+    }
+}
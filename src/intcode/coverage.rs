@@ -0,0 +1,171 @@
+/*
+ * Code and data coverage.
+ *
+ * When enabled on a machine, records how every address was used during a run:
+ * executed as an instruction's opcode, read as one of its parameters, read as
+ * data through a position mode parameter, or written. Addresses without any
+ * of these were never touched, which helps find dead code in puzzle inputs.
+ * Only touched addresses are stored, so huge sparse address spaces are fine.
+ */
+
+use std::collections::BTreeMap;
+
+use super::disassembler;
+use super::opcode::Registry;
+use super::Word;
+
+pub const EXECUTED: u8 = 1;
+pub const PARAMETER: u8 = 2;
+pub const READ: u8 = 4;
+pub const WRITTEN: u8 = 8;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Coverage {
+    flags: BTreeMap<usize, u8>,
+}
+
+impl Coverage {
+    pub fn new() -> Coverage {
+        Default::default()
+    }
+
+    pub fn mark(&mut self, address: usize, flag: u8) {
+        *self.flags.entry(address).or_insert(0) |= flag;
+    }
+
+    /// The flags recorded for an address, 0 if it was never touched.
+    pub fn get(&self, address: usize) -> u8 {
+        self.flags.get(&address).copied().unwrap_or(0)
+    }
+
+    pub fn untouched(&self, len: usize) -> Vec<usize> {
+        (0..len).filter(|&address| self.get(address) == 0).collect()
+    }
+
+    /// A disassembly of memory annotated with coverage. Each line is marked
+    /// with `x` if it was executed, `r` if it was read as data and `w` if
+    /// any of its words were written:
+    ///
+    ///    0: x--  in [9]
+    ///    9: -rw  data 0
+    ///   10: ---  data 8
+    ///
+    /// Executed addresses are always disassembled as instructions, so code
+    /// after data is still listed correctly.
+    pub fn listing(&self, registry: &Registry, memory: &[Word]) -> String {
+        let mut lines = Vec::new();
+
+        let mut address = 0;
+        while address < memory.len() {
+            let (mut text, mut size) = disassembler::instruction(registry, memory, address);
+
+            // Don't let an instruction that was never executed swallow the
+            // start of one that was.
+            let overlaps = (address + 1..address + size).any(|a| self.get(a) & EXECUTED != 0);
+            if self.get(address) & EXECUTED == 0 && overlaps {
+                text = format!("data {}", memory[address]);
+                size = 1;
+            }
+
+            let flags = (address..address + size).fold(0, |flags, a| flags | self.get(a));
+            lines.push(format!(
+                "{:>4}: {}{}{}  {}",
+                address,
+                if self.get(address) & EXECUTED != 0 {
+                    'x'
+                } else {
+                    '-'
+                },
+                if flags & READ != 0 { 'r' } else { '-' },
+                if flags & WRITTEN != 0 { 'w' } else { '-' },
+                text
+            ));
+
+            address += size;
+        }
+
+        lines.join("\n")
+    }
+
+    /// A compact map of `len` addresses, 50 to a row. Each address is shown
+    /// as `x` (executed), `p` (parameter), `w` (written), `r` (read) or `.`
+    /// (untouched), in that order of precedence. With `color`, each kind is
+    /// also colored with ANSI escape codes.
+    pub fn map(&self, len: usize, color: bool) -> String {
+        const WIDTH: usize = 50;
+
+        let mut rows = Vec::new();
+        for start in (0..len).step_by(WIDTH) {
+            let mut row = format!("{:>4}: ", start);
+            for address in start..len.min(start + WIDTH) {
+                let (symbol, code) = match self.get(address) {
+                    f if f & EXECUTED != 0 => ('x', "32"),
+                    f if f & PARAMETER != 0 => ('p', "36"),
+                    f if f & WRITTEN != 0 => ('w', "31"),
+                    f if f & READ != 0 => ('r', "34"),
+                    _ => ('.', "90"),
+                };
+
+                if color {
+                    row.push_str(&format!("\x1b[{}m{}\x1b[0m", code, symbol));
+                } else {
+                    row.push(symbol);
+                }
+            }
+            rows.push(row);
+        }
+
+        rows.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod test_coverage {
+    use super::super::{parse, Machine};
+    use super::*;
+
+    fn run(program: &str, input: Word) -> (Machine, Coverage) {
+        let mut machine = Machine::new(&parse(program));
+        machine.set_coverage(true);
+        machine.push_input(input);
+        machine.run_to_halt().unwrap();
+        let coverage = machine.coverage().unwrap().clone();
+
+        (machine, coverage)
+    }
+
+    #[test]
+    fn flags() {
+        let (_, coverage) = run("3,9,8,9,10,9,4,9,99,-1,8", 8);
+        assert_eq!(coverage.get(0), EXECUTED);
+        assert_eq!(coverage.get(1), PARAMETER);
+        assert_eq!(coverage.get(8), EXECUTED);
+        assert_eq!(coverage.get(9), READ | WRITTEN);
+        assert_eq!(coverage.get(10), READ);
+        assert_eq!(coverage.untouched(11), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn dead_code() {
+        // Jump over the output of 999 when the input isn't 0.
+        let (machine, coverage) = run("3,11,1005,11,7,104,999,104,1,99,0,0", 1);
+        assert_eq!(coverage.untouched(12), vec![5, 6, 10]);
+        assert_eq!(
+            coverage.listing(machine.registry(), &machine.memory().to_vec()),
+            "   0: x--  in [11]\n   2: x--  jt [11], 7\n   5: ---  out 999\n   7: x--  out 1\n   9: x--  hlt\n  10: ---  data 0\n  11: -rw  data 1"
+        );
+    }
+
+    #[test]
+    fn map() {
+        let (_, coverage) = run("3,11,1005,11,7,104,999,104,1,99,0,0", 1);
+        assert_eq!(coverage.map(12, false), "   0: xpxpp..xpx.w");
+    }
+
+    #[test]
+    fn disabled() {
+        let mut machine = Machine::new(&parse("99"));
+        machine.run_to_halt().unwrap();
+        assert!(machine.coverage().is_none());
+    }
+}
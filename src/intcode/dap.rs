@@ -0,0 +1,521 @@
+/*
+ * Debug Adapter Protocol server.
+ *
+ * Debugs assembler source (see `assembler`) from editors that speak the Debug
+ * Adapter Protocol, normally over stdin and stdout with `intcode-dap`. Each
+ * message is JSON with a `Content-Length` header:
+ *
+ *   Content-Length: 72\r\n
+ *   \r\n
+ *   {"seq":1,"type":"request","command":"launch","arguments":{...}}
+ *
+ * `launch` takes the source file as `program`, and optionally the values to
+ * queue as `input` and whether to `stopOnEntry`. Breakpoints are set by line,
+ * on lines with an instruction. There's a single thread with a single stack
+ * frame at the current instruction, and two scopes: the registers (the
+ * instruction pointer, step count and queued input) and memory, one variable
+ * per word, named by address and any label there. Stepping executes one
+ * instruction.
+ *
+ * Outputs are sent as output events. Running out of input stops with reason
+ * `pause` until more is queued by evaluating `input <v>[,<v>...]`, and faults
+ * stop with reason `exception` at the faulting instruction. Any other
+ * expression is an address or label to read.
+ *
+ * Requests are handled one at a time, so a `pause` can't arrive while the
+ * program is running. Instead, continuing pauses by itself after a million
+ * steps, in case the program never stops, and `pause` just reports that it's
+ * paused.
+ */
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::sync::Arc;
+
+use serde_json::{json, Value};
+
+use super::assembler::{assemble_with_map, SourceMap};
+use super::disassembler;
+use super::opcode::Registry;
+use super::{Event, Machine, Word};
+
+const THREAD: u64 = 1;
+// How many steps to run before pausing a continue.
+const CONTINUE_STEPS: u64 = 1_000_000;
+const REGISTERS: u64 = 1;
+const MEMORY: u64 = 2;
+
+#[derive(Debug)]
+struct Program {
+    path: String,
+    machine: Machine,
+    map: SourceMap,
+    // Addresses.
+    breakpoints: BTreeSet<usize>,
+    stop_on_entry: bool,
+}
+
+#[derive(Debug)]
+pub struct Adapter {
+    registry: Arc<Registry>,
+    seq: u64,
+    program: Option<Program>,
+    disconnected: bool,
+}
+
+impl Adapter {
+    pub fn new(registry: Arc<Registry>) -> Adapter {
+        Adapter {
+            registry,
+            seq: 0,
+            program: None,
+            disconnected: false,
+        }
+    }
+
+    /// Handle requests from `input` until the client disconnects or closes
+    /// it.
+    pub fn serve<R: BufRead, W: Write>(&mut self, mut input: R, mut output: W) -> io::Result<()> {
+        while !self.disconnected {
+            let request = match read_message(&mut input)? {
+                Some(request) => request,
+                None => break,
+            };
+            for message in self.handle(&request) {
+                write_message(&mut output, &message)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The response to a request, and any events it caused, in order.
+    pub fn handle(&mut self, request: &Value) -> Vec<Value> {
+        let command = request["command"].as_str().unwrap_or_default();
+        let arguments = &request["arguments"];
+
+        let mut events = Vec::new();
+        let result = match command {
+            "initialize" => Ok(json!({ "supportsConfigurationDoneRequest": true })),
+            "launch" => self.launch(arguments).map(|()| {
+                events.push(event("initialized", Value::Null));
+                Value::Null
+            }),
+            "disconnect" => {
+                self.disconnected = true;
+                Ok(Value::Null)
+            }
+            _ => match &mut self.program {
+                Some(program) => program.handle(&self.registry, command, arguments, &mut events),
+                None => Err("no program has been launched".to_string()),
+            },
+        };
+
+        let mut response = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": command,
+            "success": result.is_ok(),
+        });
+        match result {
+            Ok(Value::Null) => {}
+            Ok(body) => response["body"] = body,
+            Err(message) => response["message"] = json!(message),
+        }
+
+        let mut messages = vec![response];
+        messages.extend(events);
+        for message in &mut messages {
+            self.seq += 1;
+            message["seq"] = json!(self.seq);
+        }
+
+        messages
+    }
+
+    fn launch(&mut self, arguments: &Value) -> Result<(), String> {
+        let path = arguments["program"]
+            .as_str()
+            .ok_or("launch needs a `program`")?;
+        let source = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        let (words, map) =
+            assemble_with_map(&self.registry, &source).map_err(|e| format!("{}: {}", path, e))?;
+
+        let mut machine = Machine::with_registry(&words, Arc::clone(&self.registry));
+        for value in arguments["input"].as_array().into_iter().flatten() {
+            machine.push_input(value.as_i64().ok_or("`input` must be integers")?);
+        }
+
+        self.program = Some(Program {
+            path: path.to_string(),
+            machine,
+            map,
+            breakpoints: BTreeSet::new(),
+            stop_on_entry: arguments["stopOnEntry"].as_bool().unwrap_or(false),
+        });
+        Ok(())
+    }
+}
+
+impl Program {
+    fn handle(
+        &mut self,
+        registry: &Registry,
+        command: &str,
+        arguments: &Value,
+        events: &mut Vec<Value>,
+    ) -> Result<Value, String> {
+        match command {
+            "setBreakpoints" => Ok(self.set_breakpoints(arguments)),
+            "configurationDone" => {
+                if self.stop_on_entry {
+                    events.push(stopped("entry", None));
+                } else {
+                    self.resume(false, events);
+                }
+                Ok(Value::Null)
+            }
+            "threads" => Ok(json!({
+                "threads": [{ "id": THREAD, "name": "intcode" }]
+            })),
+            "stackTrace" => Ok(self.stack_trace(registry)),
+            "scopes" => Ok(json!({
+                "scopes": [
+                    {
+                        "name": "Registers",
+                        "variablesReference": REGISTERS,
+                        "expensive": false
+                    },
+                    {
+                        "name": "Memory",
+                        "variablesReference": MEMORY,
+                        "indexedVariables": self.machine.memory().len(),
+                        "expensive": false
+                    }
+                ]
+            })),
+            "variables" => self.variables(arguments),
+            "evaluate" => self.evaluate(arguments),
+            "continue" => {
+                self.resume(false, events);
+                Ok(json!({ "allThreadsContinued": true }))
+            }
+            "next" | "stepIn" => {
+                self.resume(true, events);
+                Ok(Value::Null)
+            }
+            // Everything runs to a stop before responding, so it's always
+            // paused already.
+            "pause" => {
+                events.push(stopped("pause", None));
+                Ok(Value::Null)
+            }
+            _ => Err(format!("unsupported command: {:?}", command)),
+        }
+    }
+
+    fn set_breakpoints(&mut self, arguments: &Value) -> Value {
+        self.breakpoints.clear();
+
+        let mut breakpoints = Vec::new();
+        for breakpoint in arguments["breakpoints"].as_array().into_iter().flatten() {
+            let line = breakpoint["line"].as_u64().unwrap_or_default() as usize;
+            match self.map.address(line) {
+                Some(address) => {
+                    self.breakpoints.insert(address);
+                    breakpoints.push(json!({ "verified": true, "line": line }));
+                }
+                None => breakpoints.push(json!({
+                    "verified": false,
+                    "line": line,
+                    "message": "no instruction on this line"
+                })),
+            }
+        }
+
+        json!({ "breakpoints": breakpoints })
+    }
+
+    // Run one instruction, or until a breakpoint or the step budget runs
+    // out, adding the events that happen on the way.
+    fn resume(&mut self, step: bool, events: &mut Vec<Value>) {
+        for _ in 0..CONTINUE_STEPS {
+            match self.machine.step() {
+                Ok(None) => {}
+                Ok(Some(Event::Output(value))) => events.push(event(
+                    "output",
+                    json!({ "category": "stdout", "output": format!("{}\n", value) }),
+                )),
+                Ok(Some(Event::NeedInput)) => {
+                    events.push(stopped("pause", Some("waiting for input".to_string())));
+                    return;
+                }
+                Ok(Some(Event::Halted)) => {
+                    events.push(event("exited", json!({ "exitCode": 0 })));
+                    events.push(event("terminated", Value::Null));
+                    return;
+                }
+                Err(error) => {
+                    events.push(stopped("exception", Some(error.to_string())));
+                    return;
+                }
+            }
+
+            if step {
+                events.push(stopped("step", None));
+                return;
+            }
+            if self.breakpoints.contains(&self.machine.pointer()) {
+                events.push(stopped("breakpoint", None));
+                return;
+            }
+        }
+
+        let text = format!("paused after {} steps", CONTINUE_STEPS);
+        events.push(stopped("pause", Some(text)));
+    }
+
+    fn stack_trace(&self, registry: &Registry) -> Value {
+        let pointer = self.machine.pointer();
+        // Just the words the instruction could use, rather than all of memory.
+        let longest = registry
+            .iter()
+            .map(|opcode| opcode.size())
+            .max()
+            .unwrap_or(1);
+        let end = self.machine.memory().len().min(pointer + longest);
+        let words: Vec<Word> = (pointer..end).map(|a| self.machine.peek(a)).collect();
+        let (name, _) = disassembler::instruction(registry, &words, 0);
+
+        let mut frame = json!({
+            "id": 0,
+            "name": name,
+            "line": 0,
+            "column": 0,
+            "instructionPointerReference": pointer.to_string(),
+        });
+        if let Some(line) = self.map.line(pointer) {
+            let name = self.path.rsplit('/').next().unwrap_or_default();
+            frame["source"] = json!({ "name": name, "path": self.path });
+            frame["line"] = json!(line);
+            frame["column"] = json!(1);
+        }
+
+        json!({ "stackFrames": [frame], "totalFrames": 1 })
+    }
+
+    fn variables(&self, arguments: &Value) -> Result<Value, String> {
+        let variable = |name: String, value: String| json!({ "name": name, "value": value, "variablesReference": 0 });
+
+        let variables: Vec<Value> = match arguments["variablesReference"].as_u64() {
+            Some(REGISTERS) => {
+                let input: Vec<String> = self
+                    .machine
+                    .pending_input()
+                    .map(|value| value.to_string())
+                    .collect();
+                vec![
+                    variable("ip".to_string(), self.machine.pointer().to_string()),
+                    variable("steps".to_string(), self.machine.steps().to_string()),
+                    variable("input".to_string(), input.join(",")),
+                ]
+            }
+            Some(MEMORY) => {
+                let len = self.machine.memory().len();
+                let start = arguments["start"].as_u64().unwrap_or(0) as usize;
+                // A count of 0 means all of them, like no count.
+                let count = match arguments["count"].as_u64() {
+                    Some(count) if count > 0 => count as usize,
+                    _ => len,
+                };
+
+                (start..len.min(start.saturating_add(count)))
+                    .map(|address| {
+                        let name = match self.map.label(address) {
+                            Some(label) => format!("[{}] {}", address, label),
+                            None => format!("[{}]", address),
+                        };
+                        variable(name, self.machine.peek(address).to_string())
+                    })
+                    .collect()
+            }
+            _ => return Err("unknown variables reference".to_string()),
+        };
+
+        Ok(json!({ "variables": variables }))
+    }
+
+    fn evaluate(&mut self, arguments: &Value) -> Result<Value, String> {
+        let expression = arguments["expression"].as_str().unwrap_or_default().trim();
+
+        let result = if let Some(text) = expression.strip_prefix("input ") {
+            let values: Result<Vec<Word>, _> =
+                text.split(',').map(|value| value.trim().parse()).collect();
+            let values = values.map_err(|_| format!("invalid input: {:?}", text))?;
+            let count = values.len();
+            self.machine.extend_input(values);
+            format!("queued {} input value(s)", count)
+        } else {
+            let inner = expression
+                .strip_prefix('[')
+                .and_then(|e| e.strip_suffix(']'))
+                .unwrap_or(expression);
+            let address = inner
+                .parse()
+                .ok()
+                .or_else(|| self.map.labels.get(inner).copied())
+                .ok_or_else(|| format!("unknown address: {:?}", expression))?;
+            self.machine.peek(address).to_string()
+        };
+
+        Ok(json!({ "result": result, "variablesReference": 0 }))
+    }
+}
+
+fn event(name: &str, body: Value) -> Value {
+    let mut event = json!({ "type": "event", "event": name });
+    if !body.is_null() {
+        event["body"] = body;
+    }
+    event
+}
+
+fn stopped(reason: &str, text: Option<String>) -> Value {
+    let mut body = json!({
+        "reason": reason,
+        "threadId": THREAD,
+        "allThreadsStopped": true,
+    });
+    if let Some(text) = text {
+        body["text"] = json!(text);
+    }
+    event("stopped", body)
+}
+
+/// Read the next message, or `None` once the input is closed.
+pub fn read_message<R: BufRead>(input: &mut R) -> io::Result<Option<Value>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(value) = line.strip_prefix("Content-Length:") {
+            length = value.trim().parse().ok();
+        }
+    }
+
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+    let length = length.ok_or_else(|| invalid("missing Content-Length".to_string()))?;
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(|e| invalid(e.to_string()))
+}
+
+pub fn write_message<W: Write>(output: &mut W, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+#[cfg(test)]
+mod test_dap {
+    use super::*;
+    use std::path::{Path, PathBuf};
+
+    fn transcript_dir() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/dap")
+    }
+
+    // Play the client's side of a recorded session, checking that the
+    // adapter sends exactly the recorded messages after each request.
+    fn replay(name: &str) {
+        let text = fs::read_to_string(transcript_dir().join(name)).unwrap();
+        let mut adapter = Adapter::new(Registry::standard());
+        let mut expected: Vec<(usize, Value)> = Vec::new();
+        let mut actual: Vec<Value> = Vec::new();
+
+        let check = |expected: &mut Vec<(usize, Value)>, actual: &mut Vec<Value>| {
+            assert_eq!(
+                actual.len(),
+                expected.len(),
+                "{}: expected {} messages, got {:#?}",
+                name,
+                expected.len(),
+                actual
+            );
+            for ((line, expected), actual) in expected.drain(..).zip(actual.drain(..)) {
+                assert_eq!(actual, expected, "{} line {}", name, line);
+            }
+        };
+
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if let Some(request) = line.strip_prefix("->") {
+                check(&mut expected, &mut actual);
+                let request: Value = serde_json::from_str(request).unwrap();
+                actual = adapter.handle(&request);
+            } else if let Some(message) = line.strip_prefix("<-") {
+                expected.push((index + 1, serde_json::from_str(message).unwrap()));
+            }
+        }
+        check(&mut expected, &mut actual);
+    }
+
+    #[test]
+    fn breakpoints_and_variables() {
+        replay("breakpoints.txt");
+    }
+
+    #[test]
+    fn stepping_and_input() {
+        replay("stepping.txt");
+    }
+
+    #[test]
+    fn pausing() {
+        replay("pausing.txt");
+    }
+
+    #[test]
+    fn errors() {
+        replay("errors.txt");
+    }
+
+    #[test]
+    fn framing() {
+        let requests = [
+            json!({ "seq": 1, "type": "request", "command": "initialize" }),
+            json!({ "seq": 2, "type": "request", "command": "disconnect" }),
+            json!({ "seq": 3, "type": "request", "command": "initialize" }),
+        ];
+        let mut input = Vec::new();
+        for request in &requests {
+            write_message(&mut input, request).unwrap();
+        }
+
+        let mut output = Vec::new();
+        let mut adapter = Adapter::new(Registry::standard());
+        adapter.serve(&input[..], &mut output).unwrap();
+
+        // Stops after the disconnect.
+        let mut output = &output[..];
+        let first = read_message(&mut output).unwrap().unwrap();
+        assert_eq!(first["command"], "initialize");
+        assert_eq!(first["body"]["supportsConfigurationDoneRequest"], true);
+        let second = read_message(&mut output).unwrap().unwrap();
+        assert_eq!(
+            (&second["command"], &second["seq"]),
+            (&json!("disconnect"), &json!(2))
+        );
+        assert_eq!(read_message(&mut output).unwrap(), None);
+    }
+}
@@ -0,0 +1,258 @@
+/*
+ * Memory-mapped devices.
+ *
+ * Devices are an opt-in extension to the machine: the standard instruction set
+ * is unchanged, but once a device is mapped onto a range of addresses, data
+ * reads through position parameters and writes to that range go to the device
+ * instead of memory, as do `Machine::read` and `Machine::write`. Instructions
+ * themselves, and their parameters, are always fetched from memory, and
+ * `Machine::peek` looks at the memory under a device without reading it.
+ *
+ *   let mut machine = Machine::new(&program);
+ *   machine.map_device(1000, Box::new(Clock::new()));
+ *   machine.map_device(2000, Box::new(Framebuffer::new(40, 6)));
+ */
+
+use std::any::Any;
+use std::fmt;
+
+use super::Word;
+
+pub trait Device: DeviceClone + fmt::Debug + Send {
+    /// The number of addresses the device occupies.
+    fn size(&self) -> usize;
+
+    /// Read the word at an offset into the device.
+    fn read(&mut self, offset: usize) -> Word;
+
+    fn write(&mut self, offset: usize, value: Word);
+
+    /// Called after every instruction the machine executes.
+    fn tick(&mut self) {}
+}
+
+// Implemented for every `Clone` device, so machines holding devices can still
+// be cloned and their devices looked up by type.
+pub trait DeviceClone {
+    fn clone_device(&self) -> Box<dyn Device>;
+    fn as_any(&self) -> &dyn Any;
+}
+
+impl<T: Device + Clone + 'static> DeviceClone for T {
+    fn clone_device(&self) -> Box<dyn Device> {
+        Box::new(self.clone())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+impl Clone for Box<dyn Device> {
+    fn clone(&self) -> Box<dyn Device> {
+        self.clone_device()
+    }
+}
+
+/// Counts the instructions executed since it was mapped or last written.
+#[derive(Clone, Debug, Default)]
+pub struct Clock {
+    ticks: Word,
+}
+
+impl Clock {
+    pub fn new() -> Clock {
+        Default::default()
+    }
+}
+
+impl Device for Clock {
+    fn size(&self) -> usize {
+        1
+    }
+
+    fn read(&mut self, _: usize) -> Word {
+        self.ticks
+    }
+
+    fn write(&mut self, _: usize, value: Word) {
+        self.ticks = value;
+    }
+
+    fn tick(&mut self) {
+        self.ticks += 1;
+    }
+}
+
+/// Produces a new pseudo-random non-negative value on every read. Writing a
+/// value reseeds it.
+#[derive(Clone, Debug)]
+pub struct Random {
+    state: u64,
+}
+
+impl Random {
+    pub fn new(seed: Word) -> Random {
+        let mut random = Random { state: 0 };
+        random.write(0, seed);
+        random
+    }
+}
+
+impl Device for Random {
+    fn size(&self) -> usize {
+        1
+    }
+
+    // xorshift64*
+    fn read(&mut self, _: usize) -> Word {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        (self.state.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 33) as Word
+    }
+
+    fn write(&mut self, _: usize, value: Word) {
+        // The state must never be zero.
+        self.state = ((value as u64) ^ 0x9e37_79b9_7f4a_7c15).max(1);
+    }
+}
+
+/// A grid of pixels, stored row by row.
+#[derive(Clone, Debug)]
+pub struct Framebuffer {
+    width: usize,
+    pixels: Vec<Word>,
+}
+
+impl Framebuffer {
+    pub fn new(width: usize, height: usize) -> Framebuffer {
+        assert!(width > 0 && height > 0, "framebuffer can't be empty");
+        Framebuffer {
+            width,
+            pixels: vec![0; width * height],
+        }
+    }
+
+    /// Panics if the pixel is outside the framebuffer.
+    pub fn pixel(&self, x: usize, y: usize) -> Word {
+        let height = self.pixels.len() / self.width;
+        assert!(
+            x < self.width && y < height,
+            "pixel ({}, {}) is outside the {}x{} framebuffer",
+            x,
+            y,
+            self.width,
+            height
+        );
+        self.pixels[y * self.width + x]
+    }
+
+    /// The pixels as text, with `#` for any that aren't 0.
+    pub fn render(&self) -> String {
+        let rows: Vec<String> = self
+            .pixels
+            .chunks(self.width)
+            .map(|row| {
+                row.iter()
+                    .map(|&pixel| if pixel != 0 { '#' } else { '.' })
+                    .collect()
+            })
+            .collect();
+
+        rows.join("\n")
+    }
+}
+
+impl Device for Framebuffer {
+    fn size(&self) -> usize {
+        self.pixels.len()
+    }
+
+    fn read(&mut self, offset: usize) -> Word {
+        self.pixels[offset]
+    }
+
+    fn write(&mut self, offset: usize, value: Word) {
+        self.pixels[offset] = value;
+    }
+}
+
+#[cfg(test)]
+mod test_device {
+    use super::super::{parse, Machine};
+    use super::*;
+
+    #[test]
+    fn clock() {
+        // Output the clock, twice.
+        let mut machine = Machine::new(&parse("4,100,1101,0,0,50,4,100,99"));
+        machine.map_device(100, Box::new(Clock::new()));
+        assert_eq!(machine.run_to_halt(), Ok(vec![0, 2]));
+        assert_eq!(machine.device::<Clock>(100).unwrap().ticks, 4);
+        assert_eq!(machine.read(100), 4);
+        assert_eq!(machine.peek(100), 0);
+        // Mapped addresses aren't backed by memory.
+        assert_eq!(machine.memory().len(), 51);
+    }
+
+    #[test]
+    fn random() {
+        let program = parse("4,100,4,100,4,100,99");
+        let run = |seed| {
+            let mut machine = Machine::new(&program);
+            machine.map_device(100, Box::new(Random::new(seed)));
+            machine.run_to_halt().unwrap()
+        };
+
+        let values = run(1);
+        assert_eq!(values, run(1));
+        assert_ne!(values, run(2));
+        assert!(values.iter().all(|&v| v >= 0));
+        assert_ne!(values[0], values[1]);
+    }
+
+    #[test]
+    fn framebuffer() {
+        // Draw two pixels, then copy the first one to the bottom left.
+        let mut machine = Machine::new(&parse("1101,0,1,100,1101,0,1,105,1001,100,0,108,99"));
+        machine.map_device(100, Box::new(Framebuffer::new(4, 3)));
+        machine.run_to_halt().unwrap();
+
+        let framebuffer = machine.device::<Framebuffer>(100).unwrap();
+        assert_eq!(framebuffer.pixel(1, 1), 1);
+        assert_eq!(framebuffer.render(), "#...\n.#..\n#...");
+        assert!(machine.device::<Clock>(100).is_none());
+    }
+
+    #[test]
+    fn clone() {
+        let mut machine = Machine::new(&parse("1101,0,1,100,99"));
+        machine.map_device(100, Box::new(Framebuffer::new(2, 1)));
+        let fresh = machine.clone();
+        machine.run_to_halt().unwrap();
+
+        assert_eq!(machine.device::<Framebuffer>(100).unwrap().render(), "#.");
+        assert_eq!(fresh.device::<Framebuffer>(100).unwrap().render(), "..");
+    }
+
+    #[test]
+    #[should_panic(expected = "device at 101 overlaps device at 100")]
+    fn overlapping() {
+        let mut machine = Machine::new(&[99]);
+        machine.map_device(100, Box::new(Framebuffer::new(2, 1)));
+        machine.map_device(101, Box::new(Clock::new()));
+    }
+
+    #[test]
+    #[should_panic(expected = "pixel (4, 0) is outside the 4x3 framebuffer")]
+    fn pixel_outside() {
+        Framebuffer::new(4, 3).pixel(4, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "framebuffer can't be empty")]
+    fn empty_framebuffer() {
+        Framebuffer::new(0, 3);
+    }
+}
@@ -0,0 +1,164 @@
+/*
+ * Memory diffs.
+ *
+ * Compare two memory snapshots, such as a program and the memory it halted
+ * with, and report only the addresses that changed:
+ *
+ *   0: 1 -> 2
+ *   3: 0 -> 70
+ *   5: - -> 9
+ *
+ * An address past the end of a snapshot is shown as `-`.
+ */
+
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Change<T> {
+    pub address: usize,
+    pub old: Option<T>,
+    pub new: Option<T>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MemoryDiff<T> {
+    pub changes: Vec<Change<T>>,
+}
+
+impl<T> MemoryDiff<T> {
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+}
+
+pub fn diff<T: Copy + PartialEq>(before: &[T], after: &[T]) -> MemoryDiff<T> {
+    let changes = (0..before.len().max(after.len()))
+        .filter_map(|address| {
+            let old = before.get(address).copied();
+            let new = after.get(address).copied();
+            if old != new {
+                Some(Change { address, old, new })
+            } else {
+                None
+            }
+        })
+        .collect();
+
+    MemoryDiff { changes }
+}
+
+impl<T: fmt::Display> fmt::Display for MemoryDiff<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let show = |value: &Option<T>| match value {
+            Some(value) => value.to_string(),
+            None => "-".to_string(),
+        };
+
+        let rows: Vec<(String, String, String)> = self
+            .changes
+            .iter()
+            .map(|c| (c.address.to_string(), show(&c.old), show(&c.new)))
+            .collect();
+        let address_width = rows.iter().map(|r| r.0.len()).max().unwrap_or(0);
+        let old_width = rows.iter().map(|r| r.1.len()).max().unwrap_or(0);
+
+        for (index, (address, old, new)) in rows.iter().enumerate() {
+            if index > 0 {
+                writeln!(f)?;
+            }
+            write!(
+                f,
+                "{:>aw$}: {:>ow$} -> {}",
+                address,
+                old,
+                new,
+                aw = address_width,
+                ow = old_width
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Assert that memory matches what's expected, panicking with a diff of only
+/// the addresses that don't.
+#[track_caller]
+pub fn assert_memory_eq<T: Copy + PartialEq + fmt::Display>(actual: &[T], expected: &[T]) {
+    let diff = diff(expected, actual);
+    if !diff.is_empty() {
+        panic!(
+            "memory differs at {} addresses (expected -> actual):\n{}",
+            diff.changes.len(),
+            diff
+        );
+    }
+}
+
+#[cfg(test)]
+mod test_diff {
+    use super::*;
+
+    #[test]
+    fn changes() {
+        let diff = diff(&[1, 0, 0, 0, 99], &[2, 0, 0, 70, 99, 0, 0, 5]);
+        assert_eq!(
+            diff.changes,
+            vec![
+                Change {
+                    address: 0,
+                    old: Some(1),
+                    new: Some(2)
+                },
+                Change {
+                    address: 3,
+                    old: Some(0),
+                    new: Some(70)
+                },
+                Change {
+                    address: 5,
+                    old: None,
+                    new: Some(0)
+                },
+                Change {
+                    address: 6,
+                    old: None,
+                    new: Some(0)
+                },
+                Change {
+                    address: 7,
+                    old: None,
+                    new: Some(5)
+                },
+            ]
+        );
+        assert_eq!(
+            diff.to_string(),
+            "0: 1 -> 2\n3: 0 -> 70\n5: - -> 0\n6: - -> 0\n7: - -> 5"
+        );
+    }
+
+    #[test]
+    fn alignment() {
+        let mut before = vec![0; 12];
+        let mut after = before.clone();
+        after[1] = 5;
+        before[11] = 1234;
+        assert_eq!(
+            diff(&before, &after).to_string(),
+            " 1:    0 -> 5\n11: 1234 -> 0"
+        );
+    }
+
+    #[test]
+    fn unchanged() {
+        assert!(diff(&[1, 2, 3], &[1, 2, 3]).is_empty());
+        assert_memory_eq(&[1, 2, 3], &[1, 2, 3]);
+    }
+
+    #[test]
+    #[should_panic(expected = "memory differs at 1 addresses (expected -> actual):\n2: 3 -> 4")]
+    fn assert_fails() {
+        assert_memory_eq(&[1, 2, 4], &[1, 2, 3]);
+    }
+}
@@ -0,0 +1,122 @@
+/*
+ * Intcode disassembler.
+ *
+ * Sweeps memory from the start, formatting every word that decodes to a
+ * registered opcode as an instruction and everything else as `data`:
+ *
+ *    0: in [9]
+ *    2: eq [9], 8, [9]
+ *    6: out [9]
+ *    8: hlt
+ *    9: data -1
+ *
+ * Position mode parameters are shown as `[address]` and immediate mode
+ * parameters as plain values. The listing is valid assembler source, so it
+ * assembles back into the same program.
+ */
+
+use super::opcode::{self, Opcode, Registry};
+use super::Word;
+
+/// Decode the instruction at `address`, if the word there is a registered
+/// opcode with valid parameter modes and all of its parameters are in memory.
+pub fn decode<'a>(registry: &'a Registry, memory: &[Word], address: usize) -> Option<&'a Opcode> {
+    let instruction = *memory.get(address)?;
+    if instruction < 0 {
+        return None;
+    }
+
+    let opcode = registry.get(instruction % 100)?;
+    if address + opcode.size() > memory.len() {
+        return None;
+    }
+
+    // Every mode must be 0 or 1, without any extra digits past the last
+    // parameter, so the instruction assembles back to the same word.
+    let modes_valid = (1..=opcode.parameters).all(|p| opcode::mode(instruction, p) <= 1);
+    let no_extra_modes = instruction / 10i64.pow(opcode.parameters as u32 + 2) == 0;
+    if modes_valid && no_extra_modes {
+        Some(opcode)
+    } else {
+        None
+    }
+}
+
+/// Format the instruction at `address` and return it with its size, or
+/// format the word there as data.
+pub fn instruction(registry: &Registry, memory: &[Word], address: usize) -> (String, usize) {
+    let opcode = match decode(registry, memory, address) {
+        Some(opcode) => opcode,
+        None => {
+            let value = memory.get(address).copied().unwrap_or(0);
+            return (format!("data {}", value), 1);
+        }
+    };
+
+    let parameters = (1..=opcode.parameters)
+        .map(|position| {
+            let value = memory[address + position];
+            if opcode::mode(memory[address], position) == 0 {
+                format!("[{}]", value)
+            } else {
+                value.to_string()
+            }
+        })
+        .collect::<Vec<String>>();
+
+    if parameters.is_empty() {
+        (opcode.mnemonic.to_string(), 1)
+    } else {
+        (
+            format!("{} {}", opcode.mnemonic, parameters.join(", ")),
+            opcode.size(),
+        )
+    }
+}
+
+pub fn disassemble(registry: &Registry, memory: &[Word]) -> String {
+    let mut lines = Vec::new();
+
+    let mut address = 0;
+    while address < memory.len() {
+        let (text, size) = instruction(registry, memory, address);
+        lines.push(format!("{:>4}: {}", address, text));
+        address += size;
+    }
+
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod test_disassembler {
+    use super::super::parse;
+    use super::*;
+
+    #[test]
+    fn listing() {
+        let registry = Registry::standard();
+        assert_eq!(
+            disassemble(&registry, &parse("3,9,8,9,10,9,4,9,99,-1,8")),
+            "   0: in [9]\n   2: eq [9], [10], [9]\n   6: out [9]\n   8: hlt\n   9: data -1\n  10: data 8"
+        );
+    }
+
+    #[test]
+    fn modes() {
+        let registry = Registry::standard();
+        assert_eq!(
+            instruction(&registry, &parse("1002,4,3,4,33"), 0),
+            ("mul [4], 3, [4]".to_string(), 4)
+        );
+    }
+
+    #[test]
+    fn invalid_as_data() {
+        let registry = Registry::standard();
+        // Unknown opcode, unknown mode, extra mode digits, truncated.
+        for program in &["42", "201,0,0,0", "10099", "1,0,0"] {
+            let memory = parse(program);
+            assert_eq!(instruction(&registry, &memory, 0).1, 1, "{}", program);
+        }
+    }
+}
@@ -0,0 +1,337 @@
+/*
+ * Core dumps.
+ *
+ * A snapshot of a machine that faulted: the error, instruction pointer, full
+ * memory, input that was queued but not read, and the last few instructions
+ * it executed (if it was keeping a history). Dumps are saved as text, in the
+ * same `key: value` style as the test vectors:
+ *
+ *   # Intcode core dump
+ *   fault: unknown opcode 42 at address 4
+ *   pointer: 4
+ *   steps: 1
+ *   input: 5,6
+ *   executed: 0 0 1101,1,1,5
+ *   memory: 1101,1,1,5,42,2
+ *
+ * Sparse memory is saved as just the pages it has, one `page: address words`
+ * line each, rather than the whole address space.
+ *
+ * A loaded dump can be listed, with the executed instructions leading up to
+ * the fault and a disassembly from the faulting address, or turned back into
+ * a machine to inspect it further.
+ */
+
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+use super::disassembler;
+use super::memory::Backend;
+use super::opcode::Registry;
+use super::parser::{parse_with, Options};
+use super::{IntcodeError, Machine, Memory, Word};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Executed {
+    pub step: u64,
+    pub address: usize,
+    /// The instruction and its raw parameters.
+    pub words: Vec<Word>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CoreDump {
+    pub fault: String,
+    pub pointer: usize,
+    pub steps: u64,
+    pub input: Vec<Word>,
+    pub executed: Vec<Executed>,
+    pub memory: Memory,
+}
+
+impl CoreDump {
+    pub fn new(machine: &Machine, error: &IntcodeError) -> CoreDump {
+        CoreDump {
+            fault: error.to_string(),
+            pointer: machine.pointer(),
+            steps: machine.steps(),
+            input: machine.pending_input().collect(),
+            executed: machine
+                .history()
+                .map(|entry| Executed {
+                    step: entry.step,
+                    address: entry.address,
+                    words: entry.words.clone(),
+                })
+                .collect(),
+            memory: machine.memory().clone(),
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<CoreDump, Box<dyn Error>> {
+        Ok(fs::read_to_string(path)?.parse()?)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn Error>> {
+        Ok(fs::write(path, self.to_string())?)
+    }
+
+    /// A machine in the state the dump was taken in, using the standard
+    /// instruction set.
+    pub fn to_machine(&self) -> Machine {
+        let mut machine = Machine::new(&[]);
+        machine.memory = self.memory.clone();
+        machine.pointer = self.pointer;
+        machine.steps = self.steps;
+        machine.extend_input(self.input.iter().copied());
+        machine
+    }
+
+    /// The fault, the instructions executed before it, and a disassembly of
+    /// `count` instructions starting at the faulting address.
+    pub fn listing(&self, registry: &Registry, count: usize) -> String {
+        let mut lines = vec![format!("fault: {}", self.fault)];
+        if !self.input.is_empty() {
+            lines.push(format!("pending input: {}", join(&self.input)));
+        }
+
+        if !self.executed.is_empty() {
+            lines.push("executed:".to_string());
+            for executed in &self.executed {
+                let (text, _) = disassembler::instruction(registry, &executed.words, 0);
+                lines.push(format!(
+                    "  {:>6} {:>5}: {}",
+                    executed.step, executed.address, text
+                ));
+            }
+        }
+
+        lines.push(format!("at step {}:", self.steps));
+        let longest = registry
+            .iter()
+            .map(|opcode| opcode.size())
+            .max()
+            .unwrap_or(1);
+        let mut address = self.pointer;
+        for index in 0..count {
+            if address >= self.memory.len() {
+                break;
+            }
+            // Only the words the instruction could use, as memory may be huge.
+            let end = self.memory.len().min(address + longest);
+            let words: Vec<Word> = (address..end).map(|a| self.memory.get(a)).collect();
+            let (text, size) = disassembler::instruction(registry, &words, 0);
+            let marker = if index == 0 { '>' } else { ' ' };
+            lines.push(format!("  {}      {:>5}: {}", marker, address, text));
+            address += size;
+        }
+
+        lines.join("\n")
+    }
+}
+
+fn join(values: &[Word]) -> String {
+    let values: Vec<String> = values.iter().map(Word::to_string).collect();
+    values.join(",")
+}
+
+impl fmt::Display for CoreDump {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "# Intcode core dump")?;
+        writeln!(f, "fault: {}", self.fault)?;
+        writeln!(f, "pointer: {}", self.pointer)?;
+        writeln!(f, "steps: {}", self.steps)?;
+        writeln!(f, "input: {}", join(&self.input))?;
+        for executed in &self.executed {
+            writeln!(
+                f,
+                "executed: {} {} {}",
+                executed.step,
+                executed.address,
+                join(&executed.words)
+            )?;
+        }
+        match self.memory.backend() {
+            Backend::Dense => writeln!(f, "memory: {}", join(&self.memory.to_vec())),
+            Backend::Sparse => {
+                for (address, words) in self.memory.pages() {
+                    writeln!(f, "page: {} {}", address, join(words))?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl FromStr for CoreDump {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<CoreDump, String> {
+        let mut fault = None;
+        let mut pointer = None;
+        let mut steps = None;
+        let mut input = Vec::new();
+        let mut executed = Vec::new();
+        let mut memory: Option<Memory> = None;
+
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let error = |message: &str| format!("line {}: {}", index + 1, message);
+            let words = |value: &str| {
+                parse_with(value, &Options::default()).map_err(|e| error(&e.to_string()))
+            };
+            let number = |value: &str| value.parse().map_err(|_| error("invalid number"));
+
+            let (key, value) = line
+                .split_once(':')
+                .ok_or_else(|| error("expected `key: value`"))?;
+            let value = value.trim();
+            match key.trim() {
+                "fault" => fault = Some(value.to_string()),
+                "pointer" => pointer = Some(number(value)?),
+                "steps" => steps = Some(number(value)?),
+                "input" if value.is_empty() => {}
+                "input" => input = words(value)?,
+                "executed" => {
+                    let parts: Vec<&str> = value.split_whitespace().collect();
+                    match parts[..] {
+                        [step, address, instruction] => executed.push(Executed {
+                            step: number(step)?,
+                            address: number(address)? as usize,
+                            words: words(instruction)?,
+                        }),
+                        _ => return Err(error("expected `executed: step address words`")),
+                    }
+                }
+                "memory" => memory = Some(Memory::new(&words(value)?)),
+                "page" => {
+                    let (address, value) = value
+                        .split_once(' ')
+                        .ok_or_else(|| error("expected `page: address words`"))?;
+                    let address: usize = number(address)? as usize;
+                    let memory =
+                        memory.get_or_insert_with(|| Memory::with_backend(&[], Backend::Sparse));
+                    if memory.backend() != Backend::Sparse {
+                        return Err(error("pages can't be mixed with `memory`"));
+                    }
+                    for (offset, word) in words(value)?.into_iter().enumerate() {
+                        memory.set(address + offset, word);
+                    }
+                }
+                other => return Err(error(&format!("unknown key {:?}", other))),
+            }
+        }
+
+        let missing = |key: &str| format!("missing {:?}", key);
+        Ok(CoreDump {
+            fault: fault.ok_or_else(|| missing("fault"))?,
+            pointer: pointer.ok_or_else(|| missing("pointer"))? as usize,
+            steps: steps.ok_or_else(|| missing("steps"))?,
+            input,
+            executed,
+            memory: memory.ok_or_else(|| missing("memory"))?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test_dump {
+    use super::super::parse;
+    use super::*;
+
+    fn fault(program: &str, input: &[Word]) -> CoreDump {
+        let mut machine = Machine::new(&parse(program));
+        machine.set_history(2);
+        machine.extend_input(input.iter().copied());
+        let error = machine.run_to_halt().unwrap_err();
+
+        CoreDump::new(&machine, &error)
+    }
+
+    #[test]
+    fn dump() {
+        let dump = fault("3,9,1101,1,1,10,104,7,42,0,0", &[5, 6]);
+        assert_eq!(
+            dump.to_string(),
+            "# Intcode core dump
+fault: unknown opcode 42 at address 8
+pointer: 8
+steps: 3
+input: 6
+executed: 1 2 1101,1,1,10
+executed: 2 6 104,7
+memory: 3,9,1101,1,1,10,104,7,42,5,2
+"
+        );
+        assert_eq!(dump.to_string().parse(), Ok(dump));
+    }
+
+    #[test]
+    fn listing() {
+        let dump = fault("3,9,1101,1,1,10,104,7,42,0,0", &[5, 6]);
+        assert_eq!(
+            dump.listing(&Registry::standard(), 3),
+            "fault: unknown opcode 42 at address 8
+pending input: 6
+executed:
+       1     2: add 1, 1, [10]
+       2     6: out 7
+at step 3:
+  >          8: data 42
+             9: data 5
+            10: data 2"
+        );
+    }
+
+    #[test]
+    fn sparse() {
+        // Writes to a huge address, then faults.
+        let mut machine = Machine::new(&parse("1101,7,0,1000000000000,42"));
+        machine.set_backend(Backend::Sparse);
+        let error = machine.run_to_halt().unwrap_err();
+        let dump = CoreDump::new(&machine, &error);
+
+        // Whole pages, apart from the last, which ends with memory.
+        let text = dump.to_string();
+        let zeros = ",0".repeat(59);
+        assert!(text.ends_with(&format!(
+            "page: 0 1101,7,0,1000000000000,42{}\npage: 1000000000000 7\n",
+            zeros
+        )));
+        let loaded: CoreDump = text.parse().unwrap();
+        assert_eq!(loaded, dump);
+        assert_eq!(loaded.to_machine().read(1_000_000_000_000), 7);
+        assert!(dump
+            .listing(&Registry::standard(), 2)
+            .ends_with(">          4: data 42\n             5: data 0"));
+    }
+
+    #[test]
+    fn to_machine() {
+        // Faults on the first run, then carries on once the opcode is fixed.
+        let mut machine = fault("104,1,42,1,1,1,99", &[]).to_machine();
+        assert_eq!(machine.pointer(), 2);
+        machine.write(2, 1101);
+        assert_eq!(machine.run_to_halt(), Ok(vec![]));
+        assert_eq!(machine.read(1), 2);
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(
+            "fault: x\npointer: 1\nsteps: 1\n".parse::<CoreDump>(),
+            Err("missing \"memory\"".to_string())
+        );
+        assert_eq!(
+            "pointer: x".parse::<CoreDump>(),
+            Err("line 1: invalid number".to_string())
+        );
+    }
+}
@@ -0,0 +1,126 @@
+/*
+ * Exit reports.
+ *
+ * Every way a run can end, along with where it ended and how much it did, so
+ * callers don't have to infer it from the outputs:
+ *
+ *   halted at address 8 after 4 steps with 1 output
+ *   faulted at address 4 after 1 step with 0 outputs: unknown opcode 42 at address 4
+ */
+
+use std::fmt;
+
+use super::IntcodeError;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ExitReason {
+    /// Executed opcode 99.
+    Halted,
+    /// The instruction pointer moved past the end of memory.
+    RanOffEnd,
+    Fault(IntcodeError),
+    StepLimit,
+    /// Needed input that wasn't given.
+    NeedInput,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExitReport {
+    pub reason: ExitReason,
+    /// The final instruction pointer.
+    pub pointer: usize,
+    pub steps: u64,
+    pub outputs: u64,
+}
+
+impl ExitReport {
+    /// Whether the program ended by itself, rather than being stopped.
+    pub fn is_halted(&self) -> bool {
+        matches!(self.reason, ExitReason::Halted | ExitReason::RanOffEnd)
+    }
+}
+
+impl fmt::Display for ExitReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let plural = |count: u64, noun: &str| match count {
+            1 => format!("1 {}", noun),
+            _ => format!("{} {}s", count, noun),
+        };
+
+        let what = match self.reason {
+            ExitReason::Halted => "halted",
+            ExitReason::RanOffEnd => "ran off the end",
+            ExitReason::Fault(_) => "faulted",
+            ExitReason::StepLimit => "reached the step limit",
+            ExitReason::NeedInput => "ran out of input",
+        };
+        write!(
+            f,
+            "{} at address {} after {} with {}",
+            what,
+            self.pointer,
+            plural(self.steps, "step"),
+            plural(self.outputs, "output")
+        )?;
+        if let ExitReason::Fault(error) = &self.reason {
+            write!(f, ": {}", error)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test_exit {
+    use super::super::{parse, Machine};
+    use super::*;
+
+    fn run(program: &str, input: &[i64]) -> ExitReport {
+        let mut machine = Machine::new(&parse(program));
+        machine.set_step_limit(Some(100));
+        machine.extend_input(input.iter().copied());
+        machine.run_to_exit().1
+    }
+
+    #[test]
+    fn reasons() {
+        assert_eq!(
+            run("3,9,8,9,10,9,4,9,99,-1,8", &[8]).to_string(),
+            "halted at address 8 after 4 steps with 1 output"
+        );
+        assert_eq!(
+            run("1101,1,1,5,104,0", &[]).to_string(),
+            "ran off the end at address 6 after 2 steps with 1 output"
+        );
+        assert_eq!(
+            run("104,1,42", &[]).to_string(),
+            "faulted at address 2 after 1 step with 1 output: unknown opcode 42 at address 2"
+        );
+        assert_eq!(
+            run("1105,1,0", &[]).to_string(),
+            "reached the step limit at address 0 after 100 steps with 0 outputs"
+        );
+        assert_eq!(
+            run("3,0,3,0,99", &[3]).to_string(),
+            "ran out of input at address 2 after 1 step with 0 outputs"
+        );
+    }
+
+    #[test]
+    fn report() {
+        let mut machine = Machine::new(&parse("104,7,104,8,99"));
+        assert_eq!(
+            machine.run_to_exit(),
+            (
+                vec![7, 8],
+                ExitReport {
+                    reason: ExitReason::Halted,
+                    pointer: 4,
+                    steps: 3,
+                    outputs: 2
+                }
+            )
+        );
+        assert!(machine.report(ExitReason::Halted).is_halted());
+    }
+}
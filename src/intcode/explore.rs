@@ -0,0 +1,235 @@
+/*
+ * Exhaustive branch exploration.
+ *
+ * Runs a program on every combination of inputs from a set of values: each
+ * time it asks for input the machine is forked once per value, and every path
+ * runs until it halts, faults or reaches the step limit. With no values to
+ * try, a path that asks for input ends there, with a missing input error.
+ * Paths that produce the same outputs and end the same way are grouped
+ * together:
+ *
+ *   outputs: 999 (halted)
+ *     inputs: 7
+ *   outputs: 1000 (halted)
+ *     inputs: 8
+ *
+ * The number of paths grows exponentially with the number of inputs read, so
+ * keep the step limit and set of values small. A program that reads input in
+ * a loop has more paths than can ever be run, so exploration stops once a
+ * given number of paths have ended, and the result says it was cut short.
+ */
+
+use std::fmt;
+
+use super::{Event, IntcodeError, Machine, Word};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Outcome {
+    pub outputs: Vec<Word>,
+    /// How the paths ended, with `StepLimit` if they were cut short.
+    pub end: Result<(), IntcodeError>,
+    /// The inputs given on each path that leads here.
+    pub inputs: Vec<Vec<Word>>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Exploration {
+    /// Distinct outcomes, in the order they were first reached.
+    pub outcomes: Vec<Outcome>,
+    /// Whether some paths weren't run because the path limit was reached.
+    pub truncated: bool,
+}
+
+impl Exploration {
+    pub fn paths(&self) -> usize {
+        self.outcomes.iter().map(|o| o.inputs.len()).sum()
+    }
+
+    fn add(&mut self, inputs: Vec<Word>, outputs: Vec<Word>, end: Result<(), IntcodeError>) {
+        match self
+            .outcomes
+            .iter_mut()
+            .find(|o| o.outputs == outputs && o.end == end)
+        {
+            Some(outcome) => outcome.inputs.push(inputs),
+            None => self.outcomes.push(Outcome {
+                outputs,
+                end,
+                inputs: vec![inputs],
+            }),
+        }
+    }
+}
+
+/// Explore every path through a program from the machine's current state,
+/// giving each path at most `step_limit` more steps and stopping after
+/// `max_paths` paths.
+pub fn explore(
+    machine: &Machine,
+    values: &[Word],
+    step_limit: u64,
+    max_paths: usize,
+) -> Exploration {
+    let mut exploration = Exploration::default();
+
+    let mut start = machine.fork();
+    start.set_step_limit(Some(machine.steps() + step_limit));
+    let mut pending = vec![(start, Vec::new(), Vec::new())];
+
+    while let Some((mut machine, inputs, mut outputs)) = pending.pop() {
+        if exploration.paths() >= max_paths {
+            exploration.truncated = true;
+            break;
+        }
+
+        loop {
+            match machine.run() {
+                Ok(Event::Output(value)) => outputs.push(value),
+                Ok(Event::NeedInput) if values.is_empty() => {
+                    let address = machine.pointer();
+                    exploration.add(inputs, outputs, Err(IntcodeError::MissingInput { address }));
+                    break;
+                }
+                Ok(Event::NeedInput) => {
+                    // In reverse, so the first value is explored first.
+                    for &value in values.iter().rev() {
+                        let mut fork = machine.fork();
+                        fork.push_input(value);
+                        let mut inputs = inputs.clone();
+                        inputs.push(value);
+                        pending.push((fork, inputs, outputs.clone()));
+                    }
+                    break;
+                }
+                Ok(Event::Halted) => {
+                    exploration.add(inputs, outputs, Ok(()));
+                    break;
+                }
+                Err(error) => {
+                    exploration.add(inputs, outputs, Err(error));
+                    break;
+                }
+            }
+        }
+    }
+
+    exploration
+}
+
+impl fmt::Display for Exploration {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let join = |values: &[Word]| {
+            if values.is_empty() {
+                "none".to_string()
+            } else {
+                let values: Vec<String> = values.iter().map(Word::to_string).collect();
+                values.join(",")
+            }
+        };
+
+        for (index, outcome) in self.outcomes.iter().enumerate() {
+            if index > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "outputs: {} (", join(&outcome.outputs))?;
+            match &outcome.end {
+                Ok(()) => write!(f, "halted)")?,
+                Err(error) => write!(f, "{})", error)?,
+            }
+            for inputs in &outcome.inputs {
+                write!(f, "\n  inputs: {}", join(inputs))?;
+            }
+        }
+        if self.truncated {
+            write!(f, "\nstopped after {} paths", self.paths())?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test_explore {
+    use super::super::parse;
+    use super::*;
+
+    #[test]
+    fn compare() {
+        // Outputs 999, 1000 or 1001 if the input is below, equal to or above 8.
+        let program = parse("3,21,1008,21,8,20,1005,20,22,107,8,21,20,1006,20,31,1106,0,36,98,0,0,1002,21,125,20,4,20,1105,1,46,104,999,1105,1,46,1101,1000,1,20,4,20,1105,1,46,98,99");
+        let exploration = explore(&Machine::new(&program), &[7, 8, 9, 100, -5], 1000, 100);
+        assert_eq!(exploration.paths(), 5);
+        assert_eq!(
+            exploration.to_string(),
+            "outputs: 999 (halted)\n  inputs: 7\n  inputs: -5\noutputs: 1000 (halted)\n  inputs: 8\noutputs: 1001 (halted)\n  inputs: 9\n  inputs: 100"
+        );
+    }
+
+    #[test]
+    fn several_inputs() {
+        // Outputs the sum of two inputs.
+        let program = parse("3,11,3,12,1,11,12,13,4,13,99,0,0,0");
+        let exploration = explore(&Machine::new(&program), &[0, 1], 100, 100);
+        let outcomes: Vec<(Vec<Word>, Vec<Vec<Word>>)> = exploration
+            .outcomes
+            .into_iter()
+            .map(|o| (o.outputs, o.inputs))
+            .collect();
+        assert_eq!(
+            outcomes,
+            vec![
+                (vec![0], vec![vec![0, 0]]),
+                (vec![1], vec![vec![0, 1], vec![1, 0]]),
+                (vec![2], vec![vec![1, 1]]),
+            ]
+        );
+    }
+
+    #[test]
+    fn limits_and_faults() {
+        // Reads input until it's 0, then faults.
+        let program = parse("3,6,1005,6,0,-1,0");
+        let exploration = explore(&Machine::new(&program), &[1, 0], 6, 100);
+        assert_eq!(
+            exploration.to_string(),
+            "outputs: none (step limit of 6 reached)\n  inputs: 1,1,1\n  inputs: 1,1,0\noutputs: none (unknown opcode -1 at address 5)\n  inputs: 1,0\n  inputs: 0"
+        );
+    }
+
+    #[test]
+    fn from_the_middle() {
+        let mut machine = Machine::new(&parse("104,5,3,9,4,9,99,0,0,0"));
+        assert_eq!(machine.run(), Ok(Event::Output(5)));
+        let exploration = explore(&machine, &[1, 2], 3, 100);
+        assert_eq!(
+            exploration.to_string(),
+            "outputs: 1 (halted)\n  inputs: 1\noutputs: 2 (halted)\n  inputs: 2"
+        );
+    }
+
+    #[test]
+    fn no_values() {
+        // Outputs 1, then reads input.
+        let exploration = explore(&Machine::new(&parse("104,1,3,0,99")), &[], 100, 100);
+        assert_eq!(exploration.paths(), 1);
+        assert_eq!(
+            exploration.to_string(),
+            "outputs: 1 (missing input value at address 2)\n  inputs: none"
+        );
+    }
+
+    #[test]
+    fn max_paths() {
+        // Reads input forever, so has 2^500 paths within the step limit.
+        let program = parse("3,5,1105,1,0,0");
+        let exploration = explore(&Machine::new(&program), &[1, 2], 1000, 3);
+        assert!(exploration.truncated);
+        assert_eq!(exploration.paths(), 3);
+        assert!(exploration.to_string().ends_with("\nstopped after 3 paths"));
+
+        // Not truncated when every path fits.
+        let exploration = explore(&Machine::new(&program), &[1, 2], 4, 4);
+        assert!(!exploration.truncated);
+        assert_eq!(exploration.paths(), 4);
+    }
+}
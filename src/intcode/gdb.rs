@@ -0,0 +1,394 @@
+/*
+ * GDB remote serial protocol stub.
+ *
+ * Lets a debugger drive a machine over a stream, normally a local TCP socket
+ * (see `intcode --gdb`). Packets are framed as `$data#checksum` and
+ * acknowledged with `+`, and the stub understands:
+ *
+ *   ?                 why the machine last stopped
+ *   g, p0             read the instruction pointer, the only register
+ *   m addr,len        read memory
+ *   M addr,len:bytes  write memory
+ *   Z0/Z1, z0/z1      set and clear breakpoints
+ *   s, c              step one instruction, or continue until a breakpoint
+ *                     or the debugger interrupts with a 0x03 byte
+ *   qRcmd             `monitor input <v>[,<v>...]` queues input values
+ *   D, k              detach, kill
+ *
+ * Anything else gets the empty reply, meaning it isn't supported.
+ *
+ * The protocol addresses bytes, so each word appears as 8 little-endian bytes:
+ * word address 3 is byte address 24. Outputs are sent to the debugger's
+ * console as `O` packets as they happen. Running out of input stops with
+ * SIGTTIN, faults stop with SIGILL (or SIGSEGV for bad addresses and
+ * SIGFPE for arithmetic overflow) at the faulting instruction, the machine's
+ * step limit stops with SIGXCPU, and an interrupt stops with SIGINT.
+ *
+ * Memory accesses are limited to the advertised packet size, longer reads
+ * returning just the start of the range, and writes past the end of dense
+ * memory are rejected, as growing it would allocate the whole range. Both
+ * reads and writes go straight to memory with `Machine::peek` and `poke`, so
+ * the debugger sees and changes the memory under any mapped device without
+ * the device noticing, and `m` returns what `M` wrote.
+ *
+ * Stock GDB has no Intcode architecture, so it's most useful with clients
+ * that send packets directly.
+ */
+
+use std::collections::BTreeSet;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+
+use super::memory::Backend;
+use super::{Event, IntcodeError, Machine, Word};
+
+const WORD_BYTES: usize = 8;
+
+// The largest packet we accept, as advertised by `qSupported`.
+const PACKET_SIZE: usize = 0x1000;
+
+// How many instructions to run between checks for an interrupt.
+const INTERRUPT_INTERVAL: u64 = 1000;
+
+// GDB's signal numbers.
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+const SIGFPE: u8 = 8;
+const SIGSEGV: u8 = 11;
+const SIGTTIN: u8 = 21;
+const SIGXCPU: u8 = 24;
+
+#[derive(Debug)]
+pub struct Stub {
+    machine: Machine,
+    // Word addresses.
+    breakpoints: BTreeSet<usize>,
+    outputs: Vec<Word>,
+    stop: String,
+    detached: bool,
+}
+
+impl Stub {
+    pub fn new(machine: Machine) -> Stub {
+        Stub {
+            machine,
+            breakpoints: BTreeSet::new(),
+            outputs: Vec::new(),
+            stop: format!("S{:02x}", SIGTRAP),
+            detached: false,
+        }
+    }
+
+    pub fn machine(&self) -> &Machine {
+        &self.machine
+    }
+
+    pub fn into_machine(self) -> Machine {
+        self.machine
+    }
+
+    /// Everything the program has output so far.
+    pub fn outputs(&self) -> &[Word] {
+        &self.outputs
+    }
+
+    /// Handle packets from `stream` until the debugger detaches or closes it.
+    pub fn serve(&mut self, mut stream: TcpStream) -> io::Result<()> {
+        while !self.detached {
+            let packet = match receive(&mut stream)? {
+                Some(packet) => packet,
+                None => break,
+            };
+            let replies = self.dispatch(&packet, &mut || interrupted(&stream));
+            for reply in replies {
+                send(&mut stream, &reply)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The replies to a single packet's data, in order.
+    pub fn handle(&mut self, packet: &str) -> Vec<String> {
+        self.dispatch(packet, &mut || false)
+    }
+
+    // Handle a packet, checking `interrupted` now and then while running.
+    fn dispatch(&mut self, packet: &str, interrupted: &mut dyn FnMut() -> bool) -> Vec<String> {
+        let (command, args) = packet.split_at(packet.len().min(1));
+        let reply = match command {
+            "?" => self.stop.clone(),
+            "g" => self.pointer(),
+            "p" => match usize::from_str_radix(args, 16) {
+                Ok(0) => self.pointer(),
+                _ => "E00".to_string(),
+            },
+            "m" => self.read_memory(args).unwrap_or_else(error),
+            "M" => self.write_memory(args).unwrap_or_else(error),
+            "Z" | "z" => self.breakpoint(command == "Z", args).unwrap_or_else(error),
+            "s" | "c" => {
+                if !args.is_empty() {
+                    match usize::from_str_radix(args, 16) {
+                        Ok(address) => self.machine.pointer = address / WORD_BYTES,
+                        Err(_) => return vec![error(())],
+                    }
+                }
+                return self.resume(command == "s", interrupted);
+            }
+            "q" => return self.query(args),
+            "H" => "OK".to_string(),
+            "D" => {
+                self.detached = true;
+                "OK".to_string()
+            }
+            "k" => {
+                self.detached = true;
+                return Vec::new();
+            }
+            _ => String::new(),
+        };
+
+        vec![reply]
+    }
+
+    fn pointer(&self) -> String {
+        hex(&(self.machine.pointer as u64).to_le_bytes())
+    }
+
+    fn read_memory(&self, args: &str) -> Result<String, ()> {
+        let (address, len) = range(args)?;
+        // Each byte takes two hex digits.
+        let len = len.min(PACKET_SIZE / 2);
+        let bytes: Vec<u8> = (address..address + len)
+            .map(|byte| self.machine.peek(byte / WORD_BYTES).to_le_bytes()[byte % WORD_BYTES])
+            .collect();
+
+        Ok(hex(&bytes))
+    }
+
+    fn write_memory(&mut self, args: &str) -> Result<String, ()> {
+        let (range_args, data) = args.split_once(':').ok_or(())?;
+        let (address, len) = range(range_args)?;
+        let data = unhex(data)?;
+        if data.len() != len {
+            return Err(());
+        }
+        let dense = self.machine.memory().backend() == Backend::Dense;
+        if dense && address + len > self.machine.memory().len() * WORD_BYTES {
+            return Err(());
+        }
+
+        for (byte, value) in (address..).zip(data) {
+            let word = byte / WORD_BYTES;
+            let mut bytes = self.machine.peek(word).to_le_bytes();
+            bytes[byte % WORD_BYTES] = value;
+            self.machine.poke(word, Word::from_le_bytes(bytes));
+        }
+
+        Ok("OK".to_string())
+    }
+
+    fn breakpoint(&mut self, insert: bool, args: &str) -> Result<String, ()> {
+        let mut parts = args.split(',');
+        let kind = parts.next().ok_or(())?;
+        if kind != "0" && kind != "1" {
+            return Ok(String::new());
+        }
+        let address = parts.next().ok_or(())?;
+        let address = usize::from_str_radix(address, 16).map_err(|_| ())? / WORD_BYTES;
+
+        if insert {
+            self.breakpoints.insert(address);
+        } else {
+            self.breakpoints.remove(&address);
+        }
+        Ok("OK".to_string())
+    }
+
+    // Run one instruction, or until a breakpoint or interrupt, sending outputs
+    // as console output before the stop reply.
+    fn resume(&mut self, step: bool, interrupted: &mut dyn FnMut() -> bool) -> Vec<String> {
+        let mut replies = Vec::new();
+        let signal = |signal: u8| format!("S{:02x}", signal);
+
+        let mut steps = 0;
+        self.stop = loop {
+            steps += 1;
+            if steps % INTERRUPT_INTERVAL == 0 && interrupted() {
+                break signal(SIGINT);
+            }
+
+            match self.machine.step() {
+                Ok(None) => {}
+                Ok(Some(Event::Output(value))) => {
+                    self.outputs.push(value);
+                    replies.push(format!("O{}", hex(format!("{}\n", value).as_bytes())));
+                }
+                Ok(Some(Event::NeedInput)) => break signal(SIGTTIN),
+                Ok(Some(Event::Halted)) => break "W00".to_string(),
+                Err(IntcodeError::NegativeAddress { .. } | IntcodeError::AddressLimit { .. }) => {
+                    break signal(SIGSEGV)
+                }
+                Err(IntcodeError::StepLimit { .. }) => break signal(SIGXCPU),
+                Err(IntcodeError::Overflow { .. }) => break signal(SIGFPE),
+                Err(_) => break signal(SIGILL),
+            }
+
+            if step || self.breakpoints.contains(&self.machine.pointer) {
+                break signal(SIGTRAP);
+            }
+        };
+
+        replies.push(self.stop.clone());
+        replies
+    }
+
+    fn query(&mut self, args: &str) -> Vec<String> {
+        let reply = if args.starts_with("Supported") {
+            format!("PacketSize={:x}", PACKET_SIZE)
+        } else if args == "Attached" {
+            "1".to_string()
+        } else if let Some(command) = args.strip_prefix("Rcmd,") {
+            self.monitor(command).unwrap_or_else(error)
+        } else {
+            String::new()
+        };
+
+        vec![reply]
+    }
+
+    fn monitor(&mut self, command: &str) -> Result<String, ()> {
+        let command = String::from_utf8(unhex(command)?).map_err(|_| ())?;
+        let values = command.strip_prefix("input ").ok_or(())?;
+        for value in values.split(',') {
+            self.machine
+                .push_input(value.trim().parse().map_err(|_| ())?);
+        }
+
+        Ok("OK".to_string())
+    }
+}
+
+fn error(_: ()) -> String {
+    "E01".to_string()
+}
+
+// An `addr,len` pair of hex numbers, for a range that doesn't overflow.
+fn range(args: &str) -> Result<(usize, usize), ()> {
+    let (address, len) = args.split_once(',').ok_or(())?;
+    let number = |text| usize::from_str_radix(text, 16).map_err(|_| ());
+    let (address, len) = (number(address)?, number(len)?);
+    address.checked_add(len).ok_or(())?;
+    Ok((address, len))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn unhex(text: &str) -> Result<Vec<u8>, ()> {
+    if !text.len().is_multiple_of(2) || !text.is_ascii() {
+        return Err(());
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&text[i..i + 2], 16).map_err(|_| ()))
+        .collect()
+}
+
+fn read_byte<S: Read>(stream: &mut S) -> io::Result<Option<u8>> {
+    let mut byte = [0];
+    match stream.read(&mut byte)? {
+        0 => Ok(None),
+        _ => Ok(Some(byte[0])),
+    }
+}
+
+// Whether the debugger has sent an interrupt, or closed the connection,
+// without waiting for it. Anything else is left to be read as a packet.
+fn interrupted(stream: &TcpStream) -> bool {
+    if stream.set_nonblocking(true).is_err() {
+        return false;
+    }
+    let mut byte = [0];
+    let interrupted = match stream.peek(&mut byte) {
+        Ok(0) => true,
+        Ok(_) if byte[0] == 0x03 => {
+            let mut stream = stream;
+            stream.read(&mut byte).is_ok()
+        }
+        _ => false,
+    };
+    stream.set_nonblocking(false).is_ok() && interrupted
+}
+
+// Receive the next packet and acknowledge it, asking for it again if the
+// checksum is wrong. Returns `None` once the stream is closed.
+fn receive<S: Read + Write>(stream: &mut S) -> io::Result<Option<String>> {
+    loop {
+        // Acknowledgements and interrupts between packets are ignored.
+        loop {
+            match read_byte(stream)? {
+                Some(b'$') => break,
+                Some(_) => {}
+                None => return Ok(None),
+            }
+        }
+
+        let mut data = Vec::new();
+        let mut sum: u8 = 0;
+        let mut escaped = false;
+        loop {
+            let byte = match read_byte(stream)? {
+                Some(b'#') => break,
+                Some(byte) => byte,
+                None => return Ok(None),
+            };
+            if data.len() > PACKET_SIZE {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "packet too long",
+                ));
+            }
+            sum = sum.wrapping_add(byte);
+            match byte {
+                b'}' if !escaped => escaped = true,
+                _ if escaped => {
+                    data.push(byte ^ 0x20);
+                    escaped = false;
+                }
+                _ => data.push(byte),
+            }
+        }
+
+        let mut checksum = [0; 2];
+        stream.read_exact(&mut checksum)?;
+        let valid = std::str::from_utf8(&checksum)
+            .ok()
+            .and_then(|text| u8::from_str_radix(text, 16).ok())
+            == Some(sum);
+
+        if valid {
+            stream.write_all(b"+")?;
+            return Ok(Some(String::from_utf8_lossy(&data).into_owned()));
+        }
+        stream.write_all(b"-")?;
+    }
+}
+
+// Send a packet, resending it until it's acknowledged.
+fn send<S: Read + Write>(stream: &mut S, data: &str) -> io::Result<()> {
+    let sum = data.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+    let packet = format!("${}#{:02x}", data, sum);
+
+    loop {
+        stream.write_all(packet.as_bytes())?;
+        stream.flush()?;
+        match read_byte(stream)? {
+            Some(b'+') => return Ok(()),
+            Some(_) => {}
+            None => return Err(io::ErrorKind::UnexpectedEof.into()),
+        }
+    }
+}
@@ -0,0 +1,373 @@
+/*
+ * Copy-on-write machine memory.
+ *
+ * Memory is split into fixed size pages shared between clones, so cloning it
+ * is constant time. The first write after a clone copies the page table, and
+ * the first write to each page copies just that page, so each copy only pays
+ * for what it changes.
+ *
+ * The page table is either dense, with an entry for every page up to the
+ * highest address written, or sparse, with entries only for pages that have
+ * been written. Dense is faster, but a program writing to an address like
+ * 10^12 needs a sparse table: dense memory only grows up to `DENSE_LIMIT`,
+ * and the machine faults on writes past it. Either way, reading memory that
+ * was never written returns 0. Iterating sparse memory still visits every
+ * address up to the highest written, so avoid it for huge address spaces.
+ */
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Arc;
+
+use super::Word;
+
+pub const PAGE_SIZE: usize = 64;
+
+/// The end of the addresses dense memory can be written at, which keeps its
+/// page table under 2 MiB.
+pub const DENSE_LIMIT: usize = 1 << 24;
+
+type Page = [Word; PAGE_SIZE];
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Backend {
+    #[default]
+    Dense,
+    Sparse,
+}
+
+#[derive(Clone)]
+enum Pages {
+    Dense(Arc<Vec<Arc<Page>>>),
+    // By page index.
+    Sparse(Arc<BTreeMap<usize, Arc<Page>>>),
+}
+
+impl Default for Pages {
+    fn default() -> Pages {
+        Pages::Dense(Default::default())
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct Memory {
+    pages: Pages,
+    len: usize,
+}
+
+impl Memory {
+    pub fn new(program: &[Word]) -> Memory {
+        Memory::with_backend(program, Backend::Dense)
+    }
+
+    pub fn with_backend(program: &[Word], backend: Backend) -> Memory {
+        let pages = program.chunks(PAGE_SIZE).map(|chunk| {
+            let mut page = [0; PAGE_SIZE];
+            page[..chunk.len()].copy_from_slice(chunk);
+            Arc::new(page)
+        });
+
+        Memory {
+            pages: match backend {
+                Backend::Dense => Pages::Dense(Arc::new(pages.collect())),
+                Backend::Sparse => Pages::Sparse(Arc::new(pages.enumerate().collect())),
+            },
+            len: program.len(),
+        }
+    }
+
+    pub fn backend(&self) -> Backend {
+        match self.pages {
+            Pages::Dense(_) => Backend::Dense,
+            Pages::Sparse(_) => Backend::Sparse,
+        }
+    }
+
+    /// The same memory using another backend. Pages are shared with this
+    /// memory rather than copied.
+    pub fn to_backend(&self, backend: Backend) -> Memory {
+        let pages = match (&self.pages, backend) {
+            (Pages::Sparse(pages), Backend::Dense) => {
+                let zero = Arc::new([0; PAGE_SIZE]);
+                let dense = (0..self.len.div_ceil(PAGE_SIZE))
+                    .map(|index| pages.get(&index).unwrap_or(&zero).clone())
+                    .collect();
+                Pages::Dense(Arc::new(dense))
+            }
+            (Pages::Dense(pages), Backend::Sparse) => {
+                Pages::Sparse(Arc::new(pages.iter().cloned().enumerate().collect()))
+            }
+            (pages, _) => pages.clone(),
+        };
+
+        Memory {
+            pages,
+            len: self.len,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Whether the address can be written, which for dense memory means it's
+    /// below `DENSE_LIMIT`.
+    pub fn fits(&self, address: usize) -> bool {
+        self.backend() == Backend::Sparse || address < DENSE_LIMIT
+    }
+
+    /// The word at an address, 0 if it's past the end.
+    pub fn get(&self, address: usize) -> Word {
+        if address >= self.len {
+            return 0;
+        }
+
+        match &self.pages {
+            Pages::Dense(pages) => pages[address / PAGE_SIZE][address % PAGE_SIZE],
+            Pages::Sparse(pages) => pages
+                .get(&(address / PAGE_SIZE))
+                .map_or(0, |page| page[address % PAGE_SIZE]),
+        }
+    }
+
+    /// Set the word at an address, growing memory to include it.
+    ///
+    /// Panics if the address doesn't fit, see `fits`.
+    pub fn set(&mut self, address: usize, value: Word) {
+        assert!(
+            self.fits(address),
+            "address {} is past the dense memory limit",
+            address
+        );
+        let index = address / PAGE_SIZE;
+        let page = match &mut self.pages {
+            Pages::Dense(pages) => {
+                let pages = Arc::make_mut(pages);
+                if index >= pages.len() {
+                    let zero = Arc::new([0; PAGE_SIZE]);
+                    pages.resize(index + 1, zero);
+                }
+                &mut pages[index]
+            }
+            Pages::Sparse(pages) => Arc::make_mut(pages)
+                .entry(index)
+                .or_insert_with(|| Arc::new([0; PAGE_SIZE])),
+        };
+        Arc::make_mut(page)[address % PAGE_SIZE] = value;
+
+        self.len = self.len.max(address + 1);
+    }
+
+    /// Make this memory equal to `original` again, using its backend. Pages
+    /// this memory has its own copy of are overwritten rather than dropped,
+    /// so they can be reused by the next write.
+    pub fn restore(&mut self, original: &Memory) {
+        match (&mut self.pages, &original.pages) {
+            (Pages::Dense(own), Pages::Dense(pages)) => {
+                let own = Arc::make_mut(own);
+                own.truncate(pages.len());
+                for (index, page) in pages.iter().enumerate() {
+                    match own.get_mut(index) {
+                        Some(own) => restore_page(own, page),
+                        None => own.push(page.clone()),
+                    }
+                }
+            }
+            (Pages::Sparse(own), Pages::Sparse(pages)) => {
+                let own = Arc::make_mut(own);
+                own.retain(|index, _| pages.contains_key(index));
+                for (&index, page) in pages.iter() {
+                    match own.get_mut(&index) {
+                        Some(own) => restore_page(own, page),
+                        None => {
+                            own.insert(index, page.clone());
+                        }
+                    }
+                }
+            }
+            (own, pages) => *own = pages.clone(),
+        }
+        self.len = original.len;
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Word> + '_ {
+        (0..self.len).map(move |address| self.get(address))
+    }
+
+    pub fn to_vec(&self) -> Vec<Word> {
+        self.iter().collect()
+    }
+
+    /// The pages in the page table, as their first address and their words
+    /// up to the end of memory. Sparse memory only has the pages that were
+    /// loaded or written, so this avoids visiting the whole address space.
+    pub fn pages(&self) -> impl Iterator<Item = (usize, &[Word])> + '_ {
+        self.page_iter().map(move |(index, page)| {
+            let start = index * PAGE_SIZE;
+            (start, &page[..PAGE_SIZE.min(self.len - start)])
+        })
+    }
+
+    /// The number of pages this memory still shares with another.
+    pub fn shared_pages(&self, other: &Memory) -> usize {
+        self.page_iter()
+            .filter(|&(index, page)| {
+                other
+                    .page(index)
+                    .is_some_and(|other| Arc::ptr_eq(page, other))
+            })
+            .count()
+    }
+
+    fn page(&self, index: usize) -> Option<&Arc<Page>> {
+        match &self.pages {
+            Pages::Dense(pages) => pages.get(index),
+            Pages::Sparse(pages) => pages.get(&index),
+        }
+    }
+
+    // Every page in the table, by index.
+    fn page_iter(&self) -> Box<dyn Iterator<Item = (usize, &Arc<Page>)> + '_> {
+        match &self.pages {
+            Pages::Dense(pages) => Box::new(pages.iter().enumerate()),
+            Pages::Sparse(pages) => Box::new(pages.iter().map(|(&index, page)| (index, page))),
+        }
+    }
+}
+
+// Make `own` equal to `page`, in place if it isn't shared.
+fn restore_page(own: &mut Arc<Page>, page: &Arc<Page>) {
+    if Arc::ptr_eq(own, page) {
+        return;
+    }
+    match Arc::get_mut(own) {
+        Some(buffer) => buffer.copy_from_slice(&page[..]),
+        None => *own = page.clone(),
+    }
+}
+
+impl fmt::Debug for Memory {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.pages {
+            Pages::Dense(_) => f.debug_list().entries(self.iter()).finish(),
+            // Could be too big to list.
+            Pages::Sparse(pages) => f
+                .debug_struct("Memory")
+                .field("len", &self.len)
+                .field("pages", pages)
+                .finish(),
+        }
+    }
+}
+
+impl PartialEq for Memory {
+    fn eq(&self, other: &Memory) -> bool {
+        // Compares the pages either table has, as missing pages are zero.
+        let zero = [0; PAGE_SIZE];
+        let matches = |a: &Memory, b: &Memory| {
+            a.page_iter()
+                .all(|(index, page)| b.page(index).map_or(&zero, |page| page) == &page[..])
+        };
+
+        self.len == other.len && matches(self, other) && matches(other, self)
+    }
+}
+
+impl Eq for Memory {}
+
+impl PartialEq<[Word]> for Memory {
+    fn eq(&self, other: &[Word]) -> bool {
+        self.len == other.len() && self.iter().eq(other.iter().copied())
+    }
+}
+
+#[cfg(test)]
+mod test_memory {
+    use super::*;
+
+    #[test]
+    fn get_and_set() {
+        for &backend in &[Backend::Dense, Backend::Sparse] {
+            let mut memory = Memory::with_backend(&[1, 2, 3], backend);
+            assert_eq!(memory.get(2), 3);
+            assert_eq!(memory.get(3), 0);
+
+            memory.set(200, 7);
+            assert_eq!(memory.len(), 201);
+            assert_eq!(memory.get(200), 7);
+            assert_eq!(memory.get(100), 0);
+            assert_eq!(&memory.to_vec()[..3], &[1, 2, 3]);
+            assert_eq!(memory.backend(), backend);
+        }
+    }
+
+    #[test]
+    fn copy_on_write() {
+        for &backend in &[Backend::Dense, Backend::Sparse] {
+            let program: Vec<Word> = (0..1000).collect();
+            let original = Memory::with_backend(&program, backend);
+            let pages = program.len().div_ceil(PAGE_SIZE);
+
+            let mut copy = original.clone();
+            assert_eq!(copy.shared_pages(&original), pages);
+
+            copy.set(1, -1);
+            copy.set(2, -2);
+            copy.set(999, -3);
+            assert_eq!(copy.shared_pages(&original), pages - 2);
+            assert_eq!(original, program[..]);
+            assert_eq!(copy.get(1), -1);
+            assert_ne!(copy, original);
+
+            // Keeps its own copies of the pages it wrote.
+            copy.set(2000, 1);
+            copy.restore(&original);
+            assert_eq!(copy, original);
+            assert_eq!(copy.shared_pages(&original), pages - 2);
+        }
+    }
+
+    #[test]
+    fn huge_addresses() {
+        let mut memory = Memory::with_backend(&[1, 2, 3], Backend::Sparse);
+        memory.set(1_000_000_000_000, 42);
+        assert_eq!(memory.len(), 1_000_000_000_001);
+        let pages: Vec<(usize, &[Word])> = memory.pages().collect();
+        assert_eq!(pages.len(), 2);
+        assert_eq!(&pages[0].1[..3], &[1, 2, 3]);
+        assert_eq!(pages[1], (1_000_000_000_000, &[42][..]));
+        assert_eq!(memory.get(1_000_000_000_000), 42);
+        assert_eq!(memory.get(999_999_999_999), 0);
+        assert_eq!(memory.get(500_000_000_000), 0);
+        assert_eq!(memory.get(2), 3);
+
+        let mut copy = memory.clone();
+        copy.set(1_000_000_000_000, 43);
+        assert_ne!(copy, memory);
+        copy.restore(&memory);
+        assert_eq!(copy, memory);
+    }
+
+    #[test]
+    fn backends() {
+        let program: Vec<Word> = (0..200).collect();
+        let mut sparse = Memory::with_backend(&program, Backend::Sparse);
+        sparse.set(1000, 5);
+
+        let dense = sparse.to_backend(Backend::Dense);
+        assert_eq!(dense.backend(), Backend::Dense);
+        assert_eq!(dense, sparse);
+        assert_eq!(dense.to_vec(), sparse.to_vec());
+        // Pages are shared, apart from the ones the sparse table never had.
+        assert_eq!(dense.shared_pages(&sparse), 5);
+
+        let mut restored = dense.clone();
+        restored.restore(&sparse);
+        assert_eq!(restored.backend(), Backend::Sparse);
+        assert_eq!(restored.to_backend(Backend::Sparse), sparse);
+    }
+}
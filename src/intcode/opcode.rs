@@ -0,0 +1,367 @@
+/*
+ * Opcode registry.
+ *
+ * Every instruction the machine, disassembler and assembler understand is
+ * described once here. The machine decodes an instruction's parameters using
+ * the table (read parameters are resolved to values according to their mode,
+ * write parameters to addresses) and then calls the opcode's `execute`
+ * function, so new opcodes can be registered without touching the core loop.
+ */
+
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::sync::{Arc, OnceLock};
+
+use super::{IntcodeError, Machine, Word};
+
+/// What the machine should do after executing an instruction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Control {
+    /// Continue with the next instruction.
+    Next,
+    /// Continue at the given address.
+    Jump(Word),
+    /// Output a value and continue with the next instruction.
+    Output(Word),
+    /// Wait for input, then execute this instruction again.
+    Wait,
+    Halt,
+}
+
+pub type Execute = fn(&mut Machine, &[Word]) -> Result<Control, IntcodeError>;
+
+/// When a conditional jump is taken, depending on its first parameter.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Condition {
+    NonZero,
+    Zero,
+}
+
+impl Condition {
+    pub fn taken(self, value: Word) -> bool {
+        match self {
+            Condition::NonZero => value != 0,
+            Condition::Zero => value == 0,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Opcode {
+    pub number: Word,
+    pub mnemonic: &'static str,
+    pub parameters: usize,
+    /// 1-based positions of the parameters that are written to. These are
+    /// passed to `execute` as addresses rather than values.
+    pub writes: &'static [usize],
+    /// Set for conditional jumps, which jump to their last parameter, so
+    /// analyses can follow them without knowing the mnemonics.
+    pub condition: Option<Condition>,
+    pub execute: Execute,
+}
+
+impl Opcode {
+    pub fn writes_to(&self, position: usize) -> bool {
+        self.writes.contains(&position)
+    }
+
+    // Instruction size including the opcode itself.
+    pub fn size(&self) -> usize {
+        self.parameters + 1
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Registry {
+    opcodes: BTreeMap<Word, Opcode>,
+}
+
+impl Registry {
+    /// An empty registry, without even a halt instruction.
+    pub fn new() -> Registry {
+        Default::default()
+    }
+
+    /// The Day 5 instruction set.
+    pub fn standard() -> Arc<Registry> {
+        static STANDARD: OnceLock<Arc<Registry>> = OnceLock::new();
+
+        STANDARD
+            .get_or_init(|| {
+                let mut registry = Registry::new();
+                for opcode in standard_opcodes() {
+                    registry.register(opcode);
+                }
+                Arc::new(registry)
+            })
+            .clone()
+    }
+
+    /// Add an opcode, replacing and returning any opcode with the same number.
+    ///
+    /// Panics if the number isn't a 2 digit opcode, or if the mnemonic is
+    /// already used by another opcode.
+    pub fn register(&mut self, opcode: Opcode) -> Option<Opcode> {
+        assert!(
+            (0..100).contains(&opcode.number),
+            "opcode must be 2 digits: {:?}",
+            opcode.number
+        );
+        if let Some(existing) = self.by_mnemonic(opcode.mnemonic) {
+            assert_eq!(
+                existing.number, opcode.number,
+                "mnemonic already registered: {:?}",
+                opcode.mnemonic
+            );
+        }
+        assert!(
+            opcode
+                .writes
+                .iter()
+                .all(|&w| w >= 1 && w <= opcode.parameters),
+            "write position out of range for {:?}",
+            opcode.mnemonic
+        );
+
+        self.opcodes.insert(opcode.number, opcode)
+    }
+
+    pub fn get(&self, number: Word) -> Option<&Opcode> {
+        self.opcodes.get(&number)
+    }
+
+    pub fn by_mnemonic(&self, mnemonic: &str) -> Option<&Opcode> {
+        self.opcodes.values().find(|o| o.mnemonic == mnemonic)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Opcode> {
+        self.opcodes.values()
+    }
+}
+
+/// An instruction set generation, to run programs with only the features an
+/// earlier day's interpreter had.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Level {
+    /// Add, multiply and halt, without parameter modes.
+    Day2,
+    /// The Day 5 opcodes, with position and immediate modes.
+    Day5,
+    /// Every opcode in the machine's registry.
+    #[default]
+    Full,
+}
+
+impl Level {
+    pub fn has_opcode(self, number: Word) -> bool {
+        match self {
+            Level::Day2 => matches!(number, 1 | 2 | 99),
+            Level::Day5 => matches!(number, 1..=8 | 99),
+            Level::Full => true,
+        }
+    }
+
+    /// Whether instructions may have mode digits at all.
+    pub fn has_modes(self) -> bool {
+        self >= Level::Day5
+    }
+}
+
+impl FromStr for Level {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Level, String> {
+        match text {
+            "day2" => Ok(Level::Day2),
+            "day5" => Ok(Level::Day5),
+            "full" => Ok(Level::Full),
+            _ => Err(format!("unknown instruction set level: {:?}", text)),
+        }
+    }
+}
+
+/// The parameter mode for a 1-based parameter position.
+pub fn mode(instruction: Word, position: usize) -> Word {
+    instruction / 10i64.pow(position as u32 + 1) % 10
+}
+
+fn standard_opcodes() -> Vec<Opcode> {
+    vec![
+        // 1,a,b,c: Result of (a + b) is stored at c.
+        Opcode {
+            number: 1,
+            mnemonic: "add",
+            parameters: 3,
+            writes: &[3],
+            condition: None,
+            execute: |machine, p| {
+                let sum = p[0].checked_add(p[1]).ok_or(IntcodeError::Overflow {
+                    address: machine.pointer(),
+                })?;
+                machine.write(p[2] as usize, sum);
+                Ok(Control::Next)
+            },
+        },
+        // 2,a,b,c: Result of (a * b) is stored at c.
+        Opcode {
+            number: 2,
+            mnemonic: "mul",
+            parameters: 3,
+            writes: &[3],
+            condition: None,
+            execute: |machine, p| {
+                let product = p[0].checked_mul(p[1]).ok_or(IntcodeError::Overflow {
+                    address: machine.pointer(),
+                })?;
+                machine.write(p[2] as usize, product);
+                Ok(Control::Next)
+            },
+        },
+        // 3,a: Read input and store at a.
+        Opcode {
+            number: 3,
+            mnemonic: "in",
+            parameters: 1,
+            writes: &[1],
+            condition: None,
+            execute: |machine, p| match machine.pop_input() {
+                Some(value) => {
+                    machine.write(p[0] as usize, value);
+                    Ok(Control::Next)
+                }
+                None => Ok(Control::Wait),
+            },
+        },
+        // 4,a: Output a.
+        Opcode {
+            number: 4,
+            mnemonic: "out",
+            parameters: 1,
+            writes: &[],
+            condition: None,
+            execute: |_, p| Ok(Control::Output(p[0])),
+        },
+        // 5,a,b: If (a != 0), jump to b.
+        Opcode {
+            number: 5,
+            mnemonic: "jt",
+            parameters: 2,
+            writes: &[],
+            condition: Some(Condition::NonZero),
+            execute: |_, p| {
+                Ok(if p[0] != 0 {
+                    Control::Jump(p[1])
+                } else {
+                    Control::Next
+                })
+            },
+        },
+        // 6,a,b: If (a == 0), jump to b.
+        Opcode {
+            number: 6,
+            mnemonic: "jf",
+            parameters: 2,
+            writes: &[],
+            condition: Some(Condition::Zero),
+            execute: |_, p| {
+                Ok(if p[0] == 0 {
+                    Control::Jump(p[1])
+                } else {
+                    Control::Next
+                })
+            },
+        },
+        // 7,a,b,c: If (a < b), store 1 at c, else store 0 at c.
+        Opcode {
+            number: 7,
+            mnemonic: "lt",
+            parameters: 3,
+            writes: &[3],
+            condition: None,
+            execute: |machine, p| {
+                machine.write(p[2] as usize, (p[0] < p[1]) as Word);
+                Ok(Control::Next)
+            },
+        },
+        // 8,a,b,c: If (a == b), store 1 at c, else store 0 at c.
+        Opcode {
+            number: 8,
+            mnemonic: "eq",
+            parameters: 3,
+            writes: &[3],
+            condition: None,
+            execute: |machine, p| {
+                machine.write(p[2] as usize, (p[0] == p[1]) as Word);
+                Ok(Control::Next)
+            },
+        },
+        Opcode {
+            number: 99,
+            mnemonic: "hlt",
+            parameters: 0,
+            writes: &[],
+            condition: None,
+            execute: |_, _| Ok(Control::Halt),
+        },
+    ]
+}
+
+#[cfg(test)]
+mod test_registry {
+    use super::super::parse;
+    use super::*;
+
+    #[test]
+    fn standard() {
+        let registry = Registry::standard();
+        let mnemonics: Vec<_> = registry.iter().map(|o| o.mnemonic).collect();
+        assert_eq!(
+            mnemonics,
+            vec!["add", "mul", "in", "out", "jt", "jf", "lt", "eq", "hlt"]
+        );
+        assert_eq!(registry.by_mnemonic("mul").unwrap().number, 2);
+        let conditions: Vec<_> = registry.iter().filter_map(|o| o.condition).collect();
+        assert_eq!(conditions, vec![Condition::NonZero, Condition::Zero]);
+        assert!(registry.get(42).is_none());
+    }
+
+    #[test]
+    fn parameter_mode() {
+        assert_eq!(mode(1234, 1), 2);
+        assert_eq!(mode(1002, 1), 0);
+        assert_eq!(mode(1234, 2), 1);
+        assert_eq!(mode(1002, 2), 1);
+        assert_eq!(mode(234, 2), 0);
+        assert_eq!(mode(1234, 3), 0);
+    }
+
+    #[test]
+    fn custom_opcode() {
+        // 42,a,b: Store the larger of a and b at b.
+        let mut registry = Registry::standard().as_ref().clone();
+        registry.register(Opcode {
+            number: 42,
+            mnemonic: "max",
+            parameters: 2,
+            writes: &[2],
+            condition: None,
+            execute: |machine, p| {
+                let value = machine.read(p[1] as usize).max(p[0]);
+                machine.write(p[1] as usize, value);
+                Ok(Control::Next)
+            },
+        });
+
+        let mut machine = Machine::with_registry(&parse("142,7,6,4,6,99,3"), Arc::new(registry));
+        assert_eq!(machine.run_to_halt(), Ok(vec![7]));
+    }
+
+    #[test]
+    #[should_panic(expected = "mnemonic already registered")]
+    fn duplicate_mnemonic() {
+        let mut registry = Registry::standard().as_ref().clone();
+        let mut opcode = registry.get(1).unwrap().clone();
+        opcode.number = 10;
+        registry.register(opcode);
+    }
+}
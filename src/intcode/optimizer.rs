@@ -0,0 +1,635 @@
+/*
+ * Peephole optimizer.
+ *
+ * Rewrites individual instructions in place, so every address stays where it
+ * was and jumps don't need relocating:
+ *
+ * - Position parameters that read memory nothing ever writes become
+ *   immediate parameters.
+ * - Arithmetic and comparisons on constants are folded into `add v, 0, [c]`,
+ *   as is multiplying by zero. Multiplying by one becomes adding zero.
+ * - Jumps on a constant condition become `jt 1, t` (`1105,1,t`), and a jump
+ *   to another unconditional jump goes straight to its target.
+ * - Runs of instructions that do nothing, like `add [a], 0, [a]` or a jump
+ *   that's never taken, are skipped with a single jump.
+ *
+ * To know what's safe to rewrite, the program is first explored from address
+ * 0 to find the instructions it can execute and the addresses they can write.
+ * An instruction is only rewritten if none of its words are ever written or
+ * read as data, and no other instruction overlaps it. An instruction whose
+ * opcode is overwritten at runtime is explored as every instruction it could
+ * become. If the program jumps somewhere that can't be worked out, or
+ * overwrites an instruction's parameters, it's left unchanged.
+ *
+ * That includes the Day 5 puzzle input, whose self-test overwrites an opcode
+ * with one that depends on the input, which could be an `in` that overwrites
+ * the first instruction's parameter.
+ */
+
+use std::collections::BTreeSet;
+use std::fmt;
+
+use super::disassembler;
+use super::opcode::{self, Opcode, Registry};
+use super::Word;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Rewrite {
+    pub address: usize,
+    pub before: Vec<Word>,
+    pub after: Vec<Word>,
+}
+
+impl fmt::Display for Rewrite {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let registry = Registry::standard();
+        write!(
+            f,
+            "{:>4}: {} -> {}",
+            self.address,
+            disassembler::instruction(&registry, &self.before, 0).0,
+            disassembler::instruction(&registry, &self.after, 0).0
+        )
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Optimized {
+    pub program: Vec<Word>,
+    pub rewrites: Vec<Rewrite>,
+}
+
+/// Optimize a program written for the standard instruction set.
+pub fn optimize(program: &[Word]) -> Optimized {
+    let registry = Registry::standard();
+    let mut optimized = Optimized {
+        program: program.to_vec(),
+        rewrites: Vec::new(),
+    };
+
+    let analysis = match Analysis::new(&registry, program) {
+        Some(analysis) => analysis,
+        None => return optimized,
+    };
+
+    let mut noops = BTreeSet::new();
+    for &address in &analysis.reachable {
+        if !analysis.rewritable(address) {
+            continue;
+        }
+        match analysis.rewrite(address) {
+            Some(Rewritten::Words(after)) => {
+                let before = program[address..address + after.len()].to_vec();
+                if after != before {
+                    optimized.program[address..address + after.len()].copy_from_slice(&after);
+                    optimized.rewrites.push(Rewrite {
+                        address,
+                        before,
+                        after,
+                    });
+                }
+            }
+            Some(Rewritten::Noop) => {
+                noops.insert(address);
+            }
+            None => {}
+        }
+    }
+
+    // Jump over each run of two or more adjacent no-ops.
+    let mut runs: Vec<Vec<usize>> = Vec::new();
+    for &address in &noops {
+        match runs.last_mut() {
+            Some(run) if analysis.end(*run.last().unwrap()) == address => run.push(address),
+            _ => runs.push(vec![address]),
+        }
+    }
+    for run in runs.into_iter().filter(|run| run.len() > 1) {
+        let address = run[0];
+        let before = program[address..analysis.end(address)].to_vec();
+        let mut after = vec![1105, 1, analysis.end(*run.last().unwrap()) as Word];
+        after.extend_from_slice(&before[3..]);
+        optimized.program[address..address + after.len()].copy_from_slice(&after);
+        optimized.rewrites.push(Rewrite {
+            address,
+            before,
+            after,
+        });
+    }
+
+    optimized.rewrites.sort_by_key(|rewrite| rewrite.address);
+    optimized
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Value {
+    Const(Word),
+    Unknown,
+}
+
+enum Rewritten {
+    Words(Vec<Word>),
+    Noop,
+}
+
+// What an instruction can do, for one of the forms it can take.
+#[derive(Default)]
+struct Effects {
+    reads: Vec<usize>,
+    writes: Vec<usize>,
+    successors: Vec<usize>,
+}
+
+struct Analysis<'a> {
+    registry: &'a Registry,
+    program: &'a [Word],
+    // Instructions that can be executed.
+    reachable: BTreeSet<usize>,
+    // Addresses that can be written, or read through position parameters.
+    written: BTreeSet<usize>,
+    read: BTreeSet<usize>,
+}
+
+impl<'a> Analysis<'a> {
+    // Explore the program assuming only the addresses written so far can
+    // change, until exploring doesn't find any new writes.
+    fn new(registry: &'a Registry, program: &'a [Word]) -> Option<Analysis<'a>> {
+        let mut analysis = Analysis {
+            registry,
+            program,
+            reachable: BTreeSet::new(),
+            written: BTreeSet::new(),
+            read: BTreeSet::new(),
+        };
+
+        loop {
+            let mut reachable = BTreeSet::new();
+            let mut written = BTreeSet::new();
+            let mut read = BTreeSet::new();
+
+            let mut pending = vec![0];
+            while let Some(address) = pending.pop() {
+                if address >= program.len() {
+                    // Running off the end halts, unless memory has grown.
+                    if analysis.written.range(program.len()..).next().is_some() {
+                        return None;
+                    }
+                    continue;
+                }
+                if !reachable.insert(address) {
+                    continue;
+                }
+
+                for effects in analysis.effects(address)? {
+                    read.extend(effects.reads);
+                    written.extend(effects.writes);
+                    pending.extend(effects.successors);
+                }
+            }
+
+            analysis.reachable = reachable;
+            analysis.read = read;
+            if written.is_subset(&analysis.written) {
+                return Some(analysis);
+            }
+            analysis.written.extend(written);
+        }
+    }
+
+    fn word(&self, address: usize) -> Word {
+        self.program.get(address).copied().unwrap_or(0)
+    }
+
+    fn modified(&self, address: usize) -> bool {
+        self.written.contains(&address)
+    }
+
+    // The forms the instruction at an address can take. Returns `None` if its
+    // parameters can be overwritten.
+    fn forms(&self, address: usize) -> Option<Vec<Word>> {
+        let forms = if self.modified(address) {
+            let mut forms = Vec::new();
+            for opcode in self.registry.iter() {
+                let reads = (1..=opcode.parameters).filter(|&p| !opcode.writes_to(p));
+                let mut instructions = vec![opcode.number];
+                for position in reads {
+                    let immediate: Vec<Word> = instructions
+                        .iter()
+                        .map(|i| i + 10i64.pow(position as u32 + 1))
+                        .collect();
+                    instructions.extend(immediate);
+                }
+                forms.extend(instructions);
+            }
+            forms
+        } else {
+            vec![self.word(address)]
+        };
+
+        if (address + 1..self.end(address)).any(|a| self.modified(a)) {
+            return None;
+        }
+
+        Some(forms)
+    }
+
+    // The address just past an instruction, or past the largest instruction
+    // it could become if it's modified.
+    fn end(&self, address: usize) -> usize {
+        let size = if self.modified(address) {
+            self.registry.iter().map(Opcode::size).max().unwrap_or(1)
+        } else {
+            self.registry
+                .get(self.word(address) % 100)
+                .map_or(1, Opcode::size)
+        };
+        address + size
+    }
+
+    fn effects(&self, address: usize) -> Option<Vec<Effects>> {
+        let mut all = Vec::new();
+        for instruction in self.forms(address)? {
+            let mut effects = Effects::default();
+            let opcode = match self.registry.get(instruction % 100) {
+                Some(opcode) => opcode,
+                None => continue,
+            };
+
+            let mut values = Vec::new();
+            let mut faults = false;
+            for position in 1..=opcode.parameters {
+                let raw = self.word(address + position);
+                if opcode.writes_to(position) {
+                    if raw < 0 {
+                        faults = true;
+                    } else {
+                        effects.writes.push(raw as usize);
+                    }
+                    continue;
+                }
+                match self.value(instruction, raw, position) {
+                    Some((value, read)) => {
+                        effects.reads.extend(read);
+                        values.push(value);
+                    }
+                    None => faults = true,
+                }
+            }
+            if faults {
+                all.push(effects);
+                continue;
+            }
+
+            let next = address + opcode.size();
+            match (opcode.mnemonic, opcode.condition) {
+                ("add" | "mul" | "lt" | "eq" | "in" | "out", _) => effects.successors.push(next),
+                (_, Some(condition)) => {
+                    if values[0] == Value::Unknown || !condition.taken(constant(values[0])) {
+                        effects.successors.push(next);
+                    }
+                    if values[0] == Value::Unknown || condition.taken(constant(values[0])) {
+                        match values[1] {
+                            Value::Const(target) if target >= 0 => {
+                                effects.successors.push(target as usize)
+                            }
+                            Value::Const(_) => {}
+                            Value::Unknown => return None,
+                        }
+                    }
+                }
+                ("hlt", _) => {}
+                _ => return None,
+            }
+            all.push(effects);
+        }
+
+        Some(all)
+    }
+
+    // The value of a read parameter and the address it reads, or `None` if
+    // decoding it faults.
+    fn value(
+        &self,
+        instruction: Word,
+        raw: Word,
+        position: usize,
+    ) -> Option<(Value, Option<usize>)> {
+        match opcode::mode(instruction, position) {
+            0 if raw >= 0 => {
+                let address = raw as usize;
+                let value = if self.modified(address) {
+                    Value::Unknown
+                } else {
+                    Value::Const(self.word(address))
+                };
+                Some((value, Some(address)))
+            }
+            1 => Some((Value::Const(raw), None)),
+            _ => None,
+        }
+    }
+
+    fn rewritable(&self, address: usize) -> bool {
+        if self.modified(address) {
+            return false;
+        }
+        let end = self.end(address);
+        end <= self.program.len()
+            && (address..end).all(|a| !self.read.contains(&a))
+            && self
+                .reachable
+                .iter()
+                .all(|&other| other == address || other >= end || self.end(other) <= address)
+    }
+
+    fn rewrite(&self, address: usize) -> Option<Rewritten> {
+        let instruction = self.word(address);
+        let opcode = self.registry.get(instruction % 100)?;
+        let raw: Vec<Word> = (1..=opcode.parameters)
+            .map(|p| self.word(address + p))
+            .collect();
+
+        // Read parameters as (mode, word), with constants made immediate.
+        let mut operands = Vec::new();
+        let mut values = Vec::new();
+        for position in 1..=opcode.parameters {
+            if opcode.writes_to(position) {
+                continue;
+            }
+            let (value, _) = self.value(instruction, raw[position - 1], position)?;
+            values.push(value);
+            operands.push(match value {
+                Value::Const(value) => (1, value),
+                Value::Unknown => (0, raw[position - 1]),
+            });
+        }
+
+        let rewritten = match (opcode.mnemonic, opcode.condition) {
+            ("add" | "mul" | "lt" | "eq", _) => {
+                let c = raw[2];
+                let folded = match (opcode.mnemonic, values[0], values[1]) {
+                    ("add", Value::Const(a), Value::Const(b)) => a.checked_add(b),
+                    ("mul", Value::Const(a), Value::Const(b)) => a.checked_mul(b),
+                    ("lt", Value::Const(a), Value::Const(b)) => Some((a < b) as Word),
+                    ("eq", Value::Const(a), Value::Const(b)) => Some((a == b) as Word),
+                    ("mul", Value::Const(0), _) | ("mul", _, Value::Const(0)) => Some(0),
+                    _ => None,
+                };
+                let identity = match opcode.mnemonic {
+                    "add" => Some(0),
+                    "mul" => Some(1),
+                    _ => None,
+                };
+
+                if let Some(value) = folded {
+                    Rewritten::Words(vec![1101, value, 0, c])
+                } else if let Some(x) = identity.and_then(|i| other_operand(&operands, i)) {
+                    if x == (0, c) && (c as usize) < self.program.len() {
+                        // Copies [c] to itself.
+                        Rewritten::Noop
+                    } else {
+                        Rewritten::Words(vec![x.0 * 100 + 1001, x.1, 0, c])
+                    }
+                } else {
+                    Rewritten::Words(encode(opcode, &operands, &[c]))
+                }
+            }
+            (_, Some(condition)) => match (values[0], values[1]) {
+                (Value::Const(c), _) if !condition.taken(c) => Rewritten::Noop,
+                (Value::Const(_), Value::Const(target)) if target >= 0 => {
+                    let target = self.thread(target as usize);
+                    if target == address + opcode.size() {
+                        Rewritten::Noop
+                    } else {
+                        Rewritten::Words(vec![1105, 1, target as Word])
+                    }
+                }
+                (Value::Unknown, Value::Const(target)) if target >= 0 => {
+                    let target = self.thread(target as usize) as Word;
+                    Rewritten::Words(encode(opcode, &[operands[0], (1, target)], &[]))
+                }
+                _ => Rewritten::Words(encode(opcode, &operands, &[])),
+            },
+            ("out", _) => Rewritten::Words(encode(opcode, &operands, &[])),
+            _ => return None,
+        };
+
+        Some(rewritten)
+    }
+
+    // Follow a chain of unconditional jumps to where it ends up.
+    fn thread(&self, mut target: usize) -> usize {
+        let mut seen = BTreeSet::new();
+        while seen.insert(target) && self.reachable.contains(&target) && !self.modified(target) {
+            let instruction = self.word(target);
+            let condition = match self.registry.get(instruction % 100) {
+                Some(opcode) => match opcode.condition {
+                    Some(condition) => condition,
+                    None => break,
+                },
+                None => break,
+            };
+            let value = self.value(instruction, self.word(target + 1), 1);
+            let next = self.value(instruction, self.word(target + 2), 2);
+            match (value, next) {
+                (Some((Value::Const(c), _)), Some((Value::Const(next), _)))
+                    if condition.taken(c) && next >= 0 =>
+                {
+                    target = next as usize
+                }
+                _ => break,
+            }
+        }
+
+        target
+    }
+}
+
+fn constant(value: Value) -> Word {
+    match value {
+        Value::Const(value) => value,
+        Value::Unknown => unreachable!(),
+    }
+}
+
+// The other operand of a binary operation, if one of them is the immediate
+// `identity`.
+fn other_operand(operands: &[(Word, Word)], identity: Word) -> Option<(Word, Word)> {
+    if operands[1] == (1, identity) {
+        Some(operands[0])
+    } else if operands[0] == (1, identity) {
+        Some(operands[1])
+    } else {
+        None
+    }
+}
+
+// Encode an instruction from its read operands as (mode, word), followed by
+// its write addresses.
+fn encode(opcode: &Opcode, operands: &[(Word, Word)], writes: &[Word]) -> Vec<Word> {
+    let mut instruction = opcode.number;
+    for (index, (mode, _)) in operands.iter().enumerate() {
+        instruction += mode * 10i64.pow(index as u32 + 2);
+    }
+
+    let mut words = vec![instruction];
+    words.extend(operands.iter().map(|&(_, word)| word));
+    words.extend_from_slice(writes);
+    words
+}
+
+#[cfg(test)]
+mod test_optimizer {
+    use std::fs;
+    use std::path::Path;
+
+    use super::super::{parse, Event, Machine};
+    use super::*;
+
+    fn rewrites(program: &str) -> Vec<String> {
+        optimize(&parse(program))
+            .rewrites
+            .iter()
+            .map(Rewrite::to_string)
+            .collect()
+    }
+
+    // Run both programs with the same input, checking they output the same
+    // values and end in the same state, apart from the rewritten code. Returns
+    // the steps each took.
+    fn side_by_side(program: &[Word], input: &[Word]) -> (u64, u64) {
+        let optimized = optimize(program);
+
+        let mut machines = [Machine::new(program), Machine::new(&optimized.program)];
+        let mut results = Vec::new();
+        for machine in &mut machines {
+            machine.extend_input(input.iter().copied());
+            let mut events = Vec::new();
+            loop {
+                match machine.run() {
+                    Ok(Event::Output(value)) => events.push(Ok(value)),
+                    Ok(Event::Halted) => break,
+                    Ok(Event::NeedInput) => {
+                        events.push(Err("needs input".to_string()));
+                        break;
+                    }
+                    Err(error) => {
+                        events.push(Err(error.to_string()));
+                        break;
+                    }
+                }
+            }
+            results.push(events);
+        }
+        assert_eq!(
+            results[0], results[1],
+            "{:?} with input {:?}",
+            program, input
+        );
+
+        let mut memory = machines[1].memory().to_vec();
+        for rewrite in &optimized.rewrites {
+            memory[rewrite.address..rewrite.address + rewrite.before.len()]
+                .copy_from_slice(&rewrite.before);
+        }
+        assert_eq!(machines[0].memory(), &memory[..]);
+
+        (machines[0].steps(), machines[1].steps())
+    }
+
+    #[test]
+    fn constants() {
+        assert_eq!(
+            rewrites("1,9,10,11,2,9,10,12,99,6,7,0,0"),
+            vec![
+                "   0: add [9], [10], [11] -> add 13, 0, [11]",
+                "   4: mul [9], [10], [12] -> add 42, 0, [12]",
+            ]
+        );
+        // Multiplying by zero or one.
+        assert_eq!(
+            rewrites("3,13,1002,13,0,13,1002,13,1,14,4,14,99,0,0"),
+            vec![
+                "   2: mul [13], 0, [13] -> add 0, 0, [13]",
+                "   6: mul [13], 1, [14] -> add [13], 0, [14]",
+            ]
+        );
+    }
+
+    #[test]
+    fn jumps() {
+        // A jump on a constant, to another jump.
+        assert_eq!(
+            rewrites("3,11,1106,0,7,99,99,1105,1,10,99,0"),
+            vec!["   2: jf 0, 7 -> jt 1, 10"]
+        );
+        // A conditional jump whose target is read from memory.
+        assert_eq!(
+            rewrites("3,9,6,9,10,104,1,99,99,0,7"),
+            vec!["   2: jf [9], [10] -> jf [9], 7"]
+        );
+    }
+
+    #[test]
+    fn noops() {
+        // Copies a value to itself twice, and never jumps.
+        assert_eq!(
+            rewrites("3,14,1001,14,0,14,1006,13,0,104,1,99,99,1,0"),
+            vec!["   2: add [14], 0, [14] -> jt 1, 9"]
+        );
+        assert_eq!(
+            side_by_side(&parse("3,14,1001,14,0,14,1006,13,0,104,1,99,99,1,0"), &[4]),
+            (5, 4)
+        );
+    }
+
+    #[test]
+    fn self_modifying() {
+        // Writes the opcode of the second instruction, so only the first is
+        // rewritten.
+        let program = parse("1101,0,1,4,0,10,9,10,99,99,0");
+        assert_eq!(
+            rewrites("1101,0,1,4,0,10,9,10,99,99,0"),
+            vec!["   0: add 0, 1, [4] -> add 1, 0, [4]"]
+        );
+        side_by_side(&program, &[]);
+        // Overwrites a jump target, so can't be explored.
+        assert_eq!(rewrites("3,4,1105,1,0,1,9,9,9,99"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn day5_examples() {
+        let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/intcode");
+        let mut programs = BTreeSet::new();
+        for entry in fs::read_dir(directory).unwrap() {
+            let path = entry.unwrap().path();
+            if path
+                .file_name()
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .starts_with("day5_")
+            {
+                let text = fs::read_to_string(&path).unwrap();
+                for line in text.lines() {
+                    if let Some(program) = line.strip_prefix("program:") {
+                        programs.insert(parse(program));
+                    }
+                }
+            }
+        }
+        assert!(programs.iter().any(|p| !optimize(p).rewrites.is_empty()));
+
+        for program in &programs {
+            for input in -2..=10 {
+                side_by_side(program, &[input]);
+            }
+        }
+    }
+
+    #[test]
+    fn day5_input() {
+        let program = parse(include_str!("../../input/2019/day5.txt"));
+        assert_eq!(optimize(&program).program, program);
+    }
+}
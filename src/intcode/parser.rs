@@ -0,0 +1,183 @@
+/*
+ * Intcode program parser.
+ *
+ * Programs are comma separated values. Whitespace (including newlines) around
+ * values is ignored, and `#` comments to the end of the line and a trailing
+ * comma can optionally be allowed. Errors say exactly which value was bad and
+ * where it was found.
+ */
+
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Options {
+    pub comments: bool,
+    pub trailing_comma: bool,
+}
+
+impl Options {
+    /// Allow both comments and a trailing comma.
+    pub fn lenient() -> Options {
+        Options {
+            comments: true,
+            trailing_comma: true,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseError {
+    /// Byte offset of the value in the input.
+    pub offset: usize,
+    /// Index of the value, which is also the address it would be loaded at.
+    pub index: usize,
+    /// The invalid value, which is empty if the value is missing.
+    pub text: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.text.is_empty() {
+            write!(
+                f,
+                "missing value {} at byte offset {}",
+                self.index, self.offset
+            )
+        } else {
+            write!(
+                f,
+                "invalid value {} {:?} at byte offset {}",
+                self.index, self.text, self.offset
+            )
+        }
+    }
+}
+
+impl Error for ParseError {}
+
+pub fn parse_with<T: FromStr>(input: &str, options: &Options) -> Result<Vec<T>, ParseError> {
+    let mut values = Vec::new();
+
+    // Byte range of the current value, and where its field started.
+    let mut token: Option<(usize, usize)> = None;
+    let mut field = 0;
+    let mut commas = 0;
+    let mut in_comment = false;
+
+    let mut push = |token: Option<(usize, usize)>, field: usize| -> Result<(), ParseError> {
+        let error = |offset: usize, text: &str, index: usize| ParseError {
+            offset,
+            index,
+            text: text.to_string(),
+        };
+
+        match token {
+            Some((start, end)) => {
+                let text = &input[start..end];
+                let value = text.parse().map_err(|_| error(start, text, values.len()))?;
+                values.push(value);
+                Ok(())
+            }
+            None => Err(error(field, "", values.len())),
+        }
+    };
+
+    for (offset, c) in input.char_indices() {
+        if in_comment {
+            in_comment = c != '\n';
+        } else if c == '#' && options.comments {
+            in_comment = true;
+        } else if c == ',' {
+            push(token.take(), field)?;
+            field = offset + 1;
+            commas += 1;
+        } else if !c.is_whitespace() {
+            let start = token.map_or(offset, |(start, _)| start);
+            token = Some((start, offset + c.len_utf8()));
+        }
+    }
+
+    // The last field may only be empty if the whole input is, or if it
+    // follows an allowed trailing comma.
+    if token.is_some() || !(commas == 0 || options.trailing_comma) {
+        push(token, field)?;
+    }
+
+    Ok(values)
+}
+
+#[cfg(test)]
+mod test_parser {
+    use super::*;
+
+    fn strict(input: &str) -> Result<Vec<i32>, ParseError> {
+        parse_with(input, &Options::default())
+    }
+
+    fn lenient(input: &str) -> Result<Vec<i32>, ParseError> {
+        parse_with(input, &Options::lenient())
+    }
+
+    #[test]
+    fn whitespace() {
+        assert_eq!(strict("1,0,0,0,99"), Ok(vec![1, 0, 0, 0, 99]));
+        assert_eq!(strict(" 1, -2 ,\n3\r\n,4\n"), Ok(vec![1, -2, 3, 4]));
+        assert_eq!(strict(""), Ok(vec![]));
+        assert_eq!(strict(" \n"), Ok(vec![]));
+    }
+
+    #[test]
+    fn comments() {
+        let input = "# Add\n1,0,0,0, # result in 0\n99 # halt";
+        assert_eq!(lenient(input), Ok(vec![1, 0, 0, 0, 99]));
+        assert_eq!(
+            strict(input).unwrap_err().to_string(),
+            "invalid value 0 \"# Add\\n1\" at byte offset 0"
+        );
+    }
+
+    #[test]
+    fn trailing_comma() {
+        assert_eq!(lenient("1,2,\n"), Ok(vec![1, 2]));
+        assert_eq!(
+            strict("1,2,\n"),
+            Err(ParseError {
+                offset: 4,
+                index: 2,
+                text: "".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn missing_value() {
+        assert_eq!(
+            lenient("1,,3").unwrap_err().to_string(),
+            "missing value 1 at byte offset 2"
+        );
+        assert_eq!(
+            lenient(",1").unwrap_err().to_string(),
+            "missing value 0 at byte offset 0"
+        );
+    }
+
+    #[test]
+    fn invalid_value() {
+        assert_eq!(
+            lenient("1,2,3 4,5"),
+            Err(ParseError {
+                offset: 4,
+                index: 2,
+                text: "3 4".to_string()
+            })
+        );
+        assert_eq!(
+            parse_with::<u32>("1,-2", &Options::default())
+                .unwrap_err()
+                .to_string(),
+            "invalid value 1 \"-2\" at byte offset 2"
+        );
+    }
+}
@@ -0,0 +1,168 @@
+/*
+ * Batch evaluation.
+ *
+ * Runs one program many times with different patches and inputs, like the
+ * noun and verb search in Day 2, across a fixed number of worker threads.
+ * Each worker keeps a single machine that it restores to the loaded program
+ * between jobs, so the memory pages a job writes are reused by the next one
+ * instead of being allocated again. Results come back in the order the jobs
+ * were given.
+ */
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use super::{IntcodeError, Machine, Word};
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Job {
+    /// Values to write before running, as (address, value).
+    pub patches: Vec<(usize, Word)>,
+    pub input: Vec<Word>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Pool {
+    machine: Machine,
+    workers: usize,
+}
+
+impl Pool {
+    pub fn new(program: &[Word], workers: usize) -> Pool {
+        Pool::with_machine(Machine::new(program), workers)
+    }
+
+    /// A pool running jobs from the state of an existing machine, keeping
+    /// its registry, devices and any step limit.
+    pub fn with_machine(machine: Machine, workers: usize) -> Pool {
+        assert!(workers > 0, "a pool needs at least one worker");
+        Pool { machine, workers }
+    }
+
+    /// Run every job until it halts, returning what it output.
+    pub fn outputs(&self, jobs: &[Job]) -> Vec<Result<Vec<Word>, IntcodeError>> {
+        self.evaluate(jobs, |_, result| result)
+    }
+
+    /// Run every job until it halts, then call `finish` with the machine and
+    /// what it output to get the job's result.
+    pub fn evaluate<R, F>(&self, jobs: &[Job], finish: F) -> Vec<R>
+    where
+        R: Send,
+        F: Fn(&Machine, Result<Vec<Word>, IntcodeError>) -> R + Sync,
+    {
+        let next = AtomicUsize::new(0);
+
+        let mut results: Vec<(usize, R)> = thread::scope(|scope| {
+            let workers: Vec<_> = (0..self.workers.min(jobs.len()))
+                .map(|_| {
+                    let template = self.machine.fork();
+                    let (next, finish) = (&next, &finish);
+                    scope.spawn(move || {
+                        let mut machine = template.fork();
+                        let mut results = Vec::new();
+                        loop {
+                            let index = next.fetch_add(1, Ordering::Relaxed);
+                            let job = match jobs.get(index) {
+                                Some(job) => job,
+                                None => return results,
+                            };
+
+                            machine.restore(&template);
+                            for &(address, value) in &job.patches {
+                                machine.write(address, value);
+                            }
+                            machine.extend_input(job.input.iter().copied());
+
+                            let outputs = machine.run_to_halt();
+                            results.push((index, finish(&machine, outputs)));
+                        }
+                    })
+                })
+                .collect();
+
+            workers
+                .into_iter()
+                .flat_map(|worker| worker.join().unwrap())
+                .collect()
+        });
+
+        results.sort_by_key(|&(index, _)| index);
+        results.into_iter().map(|(_, result)| result).collect()
+    }
+}
+
+#[cfg(test)]
+mod test_pool {
+    use super::super::parse;
+    use super::*;
+    use crate::day2;
+
+    #[test]
+    fn results_in_order() {
+        // Outputs the input times 2.
+        let pool = Pool::new(&parse("3,9,102,2,9,9,4,9,99,0"), 3);
+        let jobs: Vec<Job> = (0..100)
+            .map(|input| Job {
+                input: vec![input],
+                ..Default::default()
+            })
+            .collect();
+
+        let expected: Vec<Result<Vec<Word>, IntcodeError>> =
+            (0..100).map(|input| Ok(vec![input * 2])).collect();
+        assert_eq!(pool.outputs(&jobs), expected);
+    }
+
+    #[test]
+    fn errors_and_limits() {
+        // Loops forever on a non-zero input.
+        let mut machine = Machine::new(&parse("3,6,1005,6,2,99,0"));
+        machine.set_step_limit(Some(100));
+        let pool = Pool::with_machine(machine, 2);
+
+        let jobs = vec![
+            Job {
+                input: vec![1],
+                ..Default::default()
+            },
+            Job {
+                input: vec![0],
+                ..Default::default()
+            },
+            Job::default(),
+        ];
+        assert_eq!(
+            pool.outputs(&jobs),
+            vec![
+                Err(IntcodeError::StepLimit { steps: 100 }),
+                Ok(vec![]),
+                Err(IntcodeError::MissingInput { address: 0 }),
+            ]
+        );
+    }
+
+    #[test]
+    fn day2_search() {
+        let program = parse(include_str!("../../input/2019/day2.txt"));
+        let jobs: Vec<Job> = (0..100)
+            .flat_map(|noun| (0..100).map(move |verb| (noun, verb)))
+            .map(|(noun, verb)| Job {
+                patches: vec![(1, noun), (2, verb)],
+                ..Default::default()
+            })
+            .collect();
+
+        let results = Pool::new(&program, 4).evaluate(&jobs, |machine, result| {
+            result.is_ok() && machine.peek(0) == 19_690_720
+        });
+        let found = results.iter().position(|&found| found).unwrap();
+        let (noun, verb) = (jobs[found].patches[0].1, jobs[found].patches[1].1);
+
+        let input = day2::input_generator(include_str!("../../input/2019/day2.txt"));
+        assert_eq!(
+            (100 * noun + verb).to_string(),
+            day2::solve_part2(&input).unwrap()
+        );
+    }
+}
@@ -0,0 +1,302 @@
+/*
+ * Output provenance.
+ *
+ * Explains where an output came from using an instruction trace: the output
+ * instruction, the instruction that wrote each word it read, how that
+ * instruction's operands were derived in turn, and so on back to words of the
+ * program, immediate parameters and inputs. Outputs and inputs are numbered
+ * from 0, e.g. as formatted for output 0 of a program computing a * 10 + 1:
+ *
+ *   output 0 = 1001: step 3 at 10: out [15]
+ *     [15] = 1001: step 2 at 6: add [14], [13], [15]
+ *       [14] = 1000: step 1 at 2: mul [14], 10, [14]
+ *         [14] = 100: step 0 at 0: in [14], input 0
+ *         10: immediate
+ *       [13] = 1: program
+ *
+ * An instruction word that was modified during the run is explained along
+ * with the operands, as its own operand. Each write is only explained once,
+ * later reads of it refer back to it instead. Only data flow is followed, not
+ * the jumps that decided which instructions ran; see `taint` for that.
+ */
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use super::disassembler;
+use super::opcode::{self, Registry};
+use super::trace::TraceEntry;
+use super::Word;
+
+/// What a line of an explanation describes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operand {
+    /// The output being explained, by number.
+    Output(usize),
+    /// A word read from memory.
+    Address(usize),
+    Immediate,
+}
+
+/// Where a value came from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Source {
+    /// Memory that wasn't written during the trace, which is normally the
+    /// program as loaded, but could be a device.
+    Program,
+    Immediate,
+    /// Written or output by an executed instruction, which read the given
+    /// input if it's an `in`.
+    Step {
+        step: u64,
+        address: usize,
+        instruction: String,
+        input: Option<usize>,
+    },
+    /// Written by an instruction already explained above.
+    Repeat {
+        step: u64,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Line {
+    pub depth: usize,
+    pub operand: Operand,
+    pub value: Word,
+    pub source: Source,
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:1$}", "", self.depth * 2)?;
+        match self.operand {
+            Operand::Output(index) => write!(f, "output {} = {}: ", index, self.value)?,
+            Operand::Address(address) => write!(f, "[{}] = {}: ", address, self.value)?,
+            Operand::Immediate => write!(f, "{}: ", self.value)?,
+        }
+
+        match &self.source {
+            Source::Program => write!(f, "program"),
+            Source::Immediate => write!(f, "immediate"),
+            Source::Step {
+                step,
+                address,
+                instruction,
+                input,
+            } => {
+                write!(f, "step {} at {}: {}", step, address, instruction)?;
+                match input {
+                    Some(index) => write!(f, ", input {}", index),
+                    None => Ok(()),
+                }
+            }
+            Source::Repeat { step } => write!(f, "step {}, see above", step),
+        }
+    }
+}
+
+/// A derivation tree, as its lines in order with their depths.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Explanation {
+    pub lines: Vec<Line>,
+}
+
+impl fmt::Display for Explanation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for line in &self.lines {
+            writeln!(f, "{}", line)?;
+        }
+        Ok(())
+    }
+}
+
+/// Explain output number `output` of a trace, which should start from the
+/// beginning of the run. Returns `None` if the trace has fewer outputs.
+pub fn explain(registry: &Registry, trace: &[TraceEntry], output: usize) -> Option<Explanation> {
+    let index = trace
+        .iter()
+        .enumerate()
+        .filter(|(_, entry)| entry.output.is_some())
+        .nth(output)?
+        .0;
+
+    // The entries that wrote each address, in order, and the number of each
+    // entry's input.
+    let mut writes: HashMap<usize, Vec<usize>> = HashMap::new();
+    let mut inputs = HashMap::new();
+    for (i, entry) in trace.iter().enumerate() {
+        for &(address, _) in &entry.writes {
+            writes.entry(address).or_default().push(i);
+        }
+        if entry.input.is_some() {
+            let number = inputs.len();
+            inputs.insert(i, number);
+        }
+    }
+    // The last entry before `i` to write `address`.
+    let writer = |address: usize, i: usize| {
+        let entries = writes.get(&address)?;
+        let before = entries.partition_point(|&w| w < i);
+        before.checked_sub(1).map(|n| entries[n])
+    };
+
+    let mut explanation = Explanation::default();
+    let mut explained = HashSet::new();
+    // Lines still to add, with the entry that produced them if any. Kept in
+    // reverse, so the first operand is explained first.
+    let value = trace[index].output.unwrap();
+    let mut pending = vec![(0, Operand::Output(output), value, Some(index))];
+    while let Some((depth, operand, value, producer)) = pending.pop() {
+        let i = match producer {
+            Some(i) => i,
+            None => {
+                let source = match operand {
+                    Operand::Immediate => Source::Immediate,
+                    _ => Source::Program,
+                };
+                explanation.lines.push(Line {
+                    depth,
+                    operand,
+                    value,
+                    source,
+                });
+                continue;
+            }
+        };
+
+        let entry = &trace[i];
+        if !explained.insert(i) {
+            explanation.lines.push(Line {
+                depth,
+                operand,
+                value,
+                source: Source::Repeat { step: entry.step },
+            });
+            continue;
+        }
+
+        let (instruction, _) = disassembler::instruction(registry, &entry.words, 0);
+        explanation.lines.push(Line {
+            depth,
+            operand,
+            value,
+            source: Source::Step {
+                step: entry.step,
+                address: entry.address,
+                instruction,
+                input: inputs.get(&i).copied(),
+            },
+        });
+
+        let mut operands = Vec::new();
+        for (offset, &word) in entry.words.iter().enumerate() {
+            if let Some(w) = writer(entry.address + offset, i) {
+                operands.push((Operand::Address(entry.address + offset), word, Some(w)));
+            }
+        }
+        if let Some(opcode) = registry.get(entry.words[0] % 100) {
+            for position in 1..=opcode.parameters {
+                let parameter = entry.words[position];
+                if opcode.writes_to(position) {
+                    continue;
+                }
+                if opcode::mode(entry.words[0], position) == 1 {
+                    operands.push((Operand::Immediate, parameter, None));
+                    continue;
+                }
+
+                let address = parameter as usize;
+                let value = entry
+                    .reads
+                    .iter()
+                    .find(|&&(a, _)| a == address)
+                    .map_or(0, |&(_, value)| value);
+                operands.push((Operand::Address(address), value, writer(address, i)));
+            }
+        }
+
+        for (operand, value, producer) in operands.into_iter().rev() {
+            pending.push((depth + 1, operand, value, producer));
+        }
+    }
+
+    Some(explanation)
+}
+
+#[cfg(test)]
+mod test_provenance {
+    use super::super::{parse, Machine};
+    use super::*;
+
+    fn run(program: &str, input: &[Word], output: usize) -> Option<String> {
+        let mut machine = Machine::new(&parse(program));
+        machine.set_trace(true);
+        machine.extend_input(input.iter().copied());
+        machine.run_to_halt().unwrap();
+
+        explain(machine.registry(), machine.trace().unwrap(), output).map(|e| e.to_string())
+    }
+
+    #[test]
+    fn arithmetic() {
+        let program = "3,14,1002,14,10,14,1,14,13,15,4,15,99,1,0,0";
+        assert_eq!(
+            run(program, &[100], 0).unwrap(),
+            "output 0 = 1001: step 3 at 10: out [15]\n\
+             \x20 [15] = 1001: step 2 at 6: add [14], [13], [15]\n\
+             \x20   [14] = 1000: step 1 at 2: mul [14], 10, [14]\n\
+             \x20     [14] = 100: step 0 at 0: in [14], input 0\n\
+             \x20     10: immediate\n\
+             \x20   [13] = 1: program\n"
+        );
+        assert_eq!(run(program, &[100], 1), None);
+    }
+
+    #[test]
+    fn repeats() {
+        // Outputs the input squared twice, which reads the input twice.
+        let program = "3,11,2,11,11,12,4,12,4,12,99,0,0";
+        assert_eq!(
+            run(program, &[7], 1).unwrap(),
+            "output 1 = 49: step 3 at 8: out [12]\n\
+             \x20 [12] = 49: step 1 at 2: mul [11], [11], [12]\n\
+             \x20   [11] = 7: step 0 at 0: in [11], input 0\n\
+             \x20   [11] = 7: step 0, see above\n"
+        );
+    }
+
+    #[test]
+    fn modified_instruction() {
+        // Patches an immediate parameter of the output instruction.
+        let program = "3,7,1001,7,1,7,104,0,99";
+        assert_eq!(
+            run(program, &[4], 0).unwrap(),
+            "output 0 = 5: step 2 at 6: out 5\n\
+             \x20 [7] = 5: step 1 at 2: add [7], 1, [7]\n\
+             \x20   [7] = 4: step 0 at 0: in [7], input 0\n\
+             \x20   1: immediate\n\
+             \x20 5: immediate\n"
+        );
+    }
+
+    #[test]
+    fn day5() {
+        let mut machine = Machine::new(&parse(include_str!("../../input/2019/day5.txt")));
+        machine.set_trace(true);
+        machine.push_input(1);
+        let outputs = machine.run_to_halt().unwrap();
+
+        let trace = machine.trace().unwrap();
+        for (index, &value) in outputs.iter().enumerate() {
+            let explanation = explain(machine.registry(), trace, index).unwrap();
+            let root = &explanation.lines[0];
+            assert_eq!(root.operand, Operand::Output(index));
+            assert_eq!(root.value, value);
+            // Every line is a child of the one above it or of an ancestor.
+            for pair in explanation.lines.windows(2) {
+                assert!(pair[1].depth >= 1 && pair[1].depth <= pair[0].depth + 1);
+            }
+        }
+    }
+}
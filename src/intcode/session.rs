@@ -0,0 +1,293 @@
+/*
+ * Recorded Intcode I/O sessions.
+ *
+ * A machine with recording enabled logs every input it consumes and every
+ * output it produces, along with the step (the number of instructions
+ * executed before it) that did so. The log is saved as text:
+ *
+ *   # step, direction, value
+ *   0 in 8
+ *   4 out 1000
+ *
+ * Replaying a session runs a program with the recorded inputs and checks that
+ * it produces the same outputs at the same steps, so an interactive run can
+ * be kept as a regression test. `intcode --record` captures a session while
+ * reading input from stdin, and `intcode --replay` checks one.
+ */
+
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+use super::{Event, IntcodeError, Machine, Word};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Record {
+    Input { step: u64, value: Word },
+    Output { step: u64, value: Word },
+}
+
+impl fmt::Display for Record {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Record::Input { step, value } => write!(f, "{} in {}", step, value),
+            Record::Output { step, value } => write!(f, "{} out {}", step, value),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Session {
+    pub records: Vec<Record>,
+}
+
+impl Session {
+    /// The session recorded by a machine so far, if it's recording.
+    pub fn from_machine(machine: &Machine) -> Option<Session> {
+        machine.recording().map(|records| Session {
+            records: records.to_vec(),
+        })
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Session, Box<dyn Error>> {
+        Ok(fs::read_to_string(path)?.parse()?)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn Error>> {
+        Ok(fs::write(path, self.to_string())?)
+    }
+
+    /// Run a fresh machine through this session, feeding it the recorded
+    /// inputs whenever it asks for input.
+    pub fn replay(&self, machine: &mut Machine) -> Result<(), ReplayError> {
+        machine.set_recording(true);
+
+        let mut expected = self.records.iter();
+        let mut checked = 0;
+        loop {
+            let event = machine.run()?;
+
+            // Check everything recorded since the last event, which includes
+            // the input consumed before an output.
+            let recorded = machine.recording().unwrap();
+            for actual in &recorded[checked..] {
+                match expected.next() {
+                    Some(record) if record == actual => checked += 1,
+                    record => {
+                        return Err(ReplayError::Diverged {
+                            index: checked,
+                            expected: record.copied(),
+                            actual: Some(*actual),
+                        })
+                    }
+                }
+            }
+
+            match event {
+                Event::Output(_) => {}
+                Event::NeedInput => match expected.as_slice().first() {
+                    Some(&Record::Input { value, .. }) => machine.push_input(value),
+                    record => {
+                        return Err(ReplayError::Diverged {
+                            index: checked,
+                            expected: record.copied(),
+                            actual: None,
+                        })
+                    }
+                },
+                Event::Halted => {
+                    return match expected.next() {
+                        None => Ok(()),
+                        record => Err(ReplayError::Diverged {
+                            index: checked,
+                            expected: record.copied(),
+                            actual: None,
+                        }),
+                    };
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for Session {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "# step, direction, value")?;
+        for record in &self.records {
+            writeln!(f, "{}", record)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Session {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Session, String> {
+        let mut records = Vec::new();
+
+        for (index, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let error = || format!("line {}: invalid record: {:?}", index + 1, line);
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            let (step, direction, value) = match parts[..] {
+                [step, direction, value] => (
+                    step.parse().map_err(|_| error())?,
+                    direction,
+                    value.parse().map_err(|_| error())?,
+                ),
+                _ => return Err(error()),
+            };
+
+            records.push(match direction {
+                "in" => Record::Input { step, value },
+                "out" => Record::Output { step, value },
+                _ => return Err(error()),
+            });
+        }
+
+        Ok(Session { records })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ReplayError {
+    Intcode(IntcodeError),
+    /// The run stopped matching the session at the record with this index.
+    /// `None` means the session or the run ended early.
+    Diverged {
+        index: usize,
+        expected: Option<Record>,
+        actual: Option<Record>,
+    },
+}
+
+impl From<IntcodeError> for ReplayError {
+    fn from(error: IntcodeError) -> ReplayError {
+        ReplayError::Intcode(error)
+    }
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let show = |record: &Option<Record>| match record {
+            Some(record) => format!("`{}`", record),
+            None => "nothing".to_string(),
+        };
+
+        match self {
+            ReplayError::Intcode(error) => write!(f, "{}", error),
+            ReplayError::Diverged {
+                index,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "replay diverged at record {}: expected {}, got {}",
+                index,
+                show(expected),
+                show(actual)
+            ),
+        }
+    }
+}
+
+impl Error for ReplayError {}
+
+#[cfg(test)]
+mod test_session {
+    use super::super::parse;
+    use super::*;
+
+    const PROGRAM: &str = "3,21,1008,21,8,20,1005,20,22,107,8,21,20,1006,20,31,1106,0,36,98,0,0,1002,21,125,20,4,20,1105,1,46,104,999,1105,1,46,1101,1000,1,20,4,20,1105,1,46,98,99";
+
+    fn record(input: Word) -> Session {
+        let mut machine = Machine::new(&parse(PROGRAM));
+        machine.set_recording(true);
+        machine.push_input(input);
+        machine.run_to_halt().unwrap();
+
+        Session::from_machine(&machine).unwrap()
+    }
+
+    #[test]
+    fn recording() {
+        let session = record(8);
+        assert_eq!(
+            session.records,
+            vec![
+                Record::Input { step: 0, value: 8 },
+                Record::Output {
+                    step: 4,
+                    value: 1000
+                },
+            ]
+        );
+        assert_eq!(
+            session.to_string(),
+            "# step, direction, value\n0 in 8\n4 out 1000\n"
+        );
+        assert_eq!(session.to_string().parse(), Ok(session));
+    }
+
+    #[test]
+    fn replay() {
+        let session = record(9);
+        assert_eq!(session.replay(&mut Machine::new(&parse(PROGRAM))), Ok(()));
+    }
+
+    #[test]
+    fn replay_diverged() {
+        // The program outputs 1000 for this input, not 999.
+        let session: Session = "0 in 8\n4 out 999\n".parse().unwrap();
+        assert_eq!(
+            session
+                .replay(&mut Machine::new(&parse(PROGRAM)))
+                .unwrap_err()
+                .to_string(),
+            "replay diverged at record 1: expected `4 out 999`, got `4 out 1000`"
+        );
+
+        // The session ends before the program asks for input.
+        let session = Session::default();
+        assert_eq!(
+            session.replay(&mut Machine::new(&parse(PROGRAM))),
+            Err(ReplayError::Diverged {
+                index: 0,
+                expected: None,
+                actual: None
+            })
+        );
+    }
+
+    #[test]
+    fn day5_sessions() {
+        let program = parse(include_str!("../../input/2019/day5.txt"));
+        for name in &["day5_part1.txt", "day5_part2.txt"] {
+            let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("tests/sessions")
+                .join(name);
+            let session = Session::load(&path).unwrap();
+            assert_eq!(
+                session.replay(&mut Machine::new(&program)),
+                Ok(()),
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(
+            "0 in 8\n1 sideways 2".parse::<Session>(),
+            Err("line 2: invalid record: \"1 sideways 2\"".to_string())
+        );
+    }
+}
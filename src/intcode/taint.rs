@@ -0,0 +1,204 @@
+/*
+ * Taint tracking.
+ *
+ * When enabled on a machine, every memory word carries the set of inputs
+ * (numbered from 0 in the order they're read) whose values flowed into it.
+ * An instruction's result is tainted by everything it read: the input it
+ * consumed, the words its position mode parameters pointed to, and its own
+ * instruction and parameter words, in case the program modified them. Each
+ * output records its taint, e.g.:
+ *
+ *   out 1000 at step 12: input 0
+ *   out 7 at step 13: no inputs
+ *
+ * Jumps taint the control flow: once a conditional jump depends on an input,
+ * every later write and output depends on it too, whichever way it went.
+ * This never forgets a jump, so it can blame inputs for outputs that only
+ * came after a branch rather than depending on it.
+ */
+
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+
+use super::disassembler;
+use super::opcode::Registry;
+use super::trace::TraceEntry;
+use super::Word;
+
+/// Input indices.
+pub type Inputs = BTreeSet<usize>;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TaintedOutput {
+    pub step: u64,
+    pub value: Word,
+    pub inputs: Inputs,
+}
+
+impl fmt::Display for TaintedOutput {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "out {} at step {}: ", self.value, self.step)?;
+        if self.inputs.is_empty() {
+            return write!(f, "no inputs");
+        }
+
+        let inputs: Vec<String> = self.inputs.iter().map(usize::to_string).collect();
+        let noun = if inputs.len() == 1 { "input" } else { "inputs" };
+        write!(f, "{} {}", noun, inputs.join(","))
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Taint {
+    // Only words with a non-empty taint.
+    words: HashMap<usize, Inputs>,
+    control: Inputs,
+    inputs: usize,
+    outputs: Vec<TaintedOutput>,
+}
+
+impl Taint {
+    pub fn new() -> Taint {
+        Default::default()
+    }
+
+    /// The inputs that flowed into the word at `address`.
+    pub fn word(&self, address: usize) -> Inputs {
+        self.words.get(&address).cloned().unwrap_or_default()
+    }
+
+    /// The inputs that every jump so far has depended on.
+    pub fn control(&self) -> &Inputs {
+        &self.control
+    }
+
+    pub fn outputs(&self) -> &[TaintedOutput] {
+        &self.outputs
+    }
+
+    /// Propagate taint through an executed instruction.
+    pub fn update(&mut self, registry: &Registry, entry: &TraceEntry) {
+        let mut flow = self.control.clone();
+        let instruction = entry.address..entry.address + entry.words.len();
+        let reads = entry.reads.iter().map(|&(address, _)| address);
+        for address in instruction.chain(reads) {
+            if let Some(inputs) = self.words.get(&address) {
+                flow.extend(inputs);
+            }
+        }
+
+        if entry.input.is_some() {
+            flow.insert(self.inputs);
+            self.inputs += 1;
+        }
+
+        for &(address, _) in &entry.writes {
+            if flow.is_empty() {
+                self.words.remove(&address);
+            } else {
+                self.words.insert(address, flow.clone());
+            }
+        }
+        if let Some(value) = entry.output {
+            self.outputs.push(TaintedOutput {
+                step: entry.step,
+                value,
+                inputs: flow.clone(),
+            });
+        }
+
+        let conditional = disassembler::decode(registry, &entry.words, 0)
+            .is_some_and(|opcode| opcode.condition.is_some());
+        if conditional || entry.jumped() {
+            self.control = flow;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_taint {
+    use super::super::{parse, Machine};
+    use super::*;
+
+    fn run(program: &str, input: &[Word]) -> Vec<String> {
+        let mut machine = Machine::new(&parse(program));
+        machine.set_taint(true);
+        machine.extend_input(input.iter().copied());
+        machine.run_to_halt().unwrap();
+
+        let taint = machine.taint().unwrap();
+        taint.outputs().iter().map(|o| o.to_string()).collect()
+    }
+
+    #[test]
+    fn arithmetic() {
+        // Outputs a * b, a + 1, and 7.
+        assert_eq!(
+            run(
+                "3,19,3,20,2,19,20,21,4,21,101,1,19,21,4,21,104,7,99,0,0,0",
+                &[3, 4]
+            ),
+            vec![
+                "out 12 at step 3: inputs 0,1",
+                "out 4 at step 5: input 0",
+                "out 7 at step 6: no inputs"
+            ]
+        );
+    }
+
+    #[test]
+    fn unused_input() {
+        // Reads an input but never uses it.
+        assert_eq!(
+            run("104,7,3,9,104,8,99", &[1]),
+            vec!["out 7 at step 0: no inputs", "out 8 at step 2: no inputs"]
+        );
+    }
+
+    #[test]
+    fn jumps() {
+        // Outputs 1 or 2 depending on whether the input is 0, then 3.
+        let program = "3,15,1006,15,10,104,1,1105,1,12,104,2,104,3,99,0";
+        assert_eq!(
+            run(program, &[0]),
+            vec!["out 2 at step 2: input 0", "out 3 at step 3: input 0"]
+        );
+
+        // An untainted jump doesn't taint anything.
+        assert_eq!(
+            run("3,9,1105,1,5,104,5,99,0,0", &[1]),
+            vec!["out 5 at step 2: no inputs"]
+        );
+    }
+
+    #[test]
+    fn memory() {
+        // Copies the input into one word and a constant over another.
+        let mut machine = Machine::new(&parse("3,11,1001,11,0,12,1101,1,1,11,99,0,0"));
+        machine.set_taint(true);
+        machine.push_input(9);
+        machine.run_to_halt().unwrap();
+
+        let taint = machine.taint().unwrap();
+        assert_eq!(taint.word(12), [0].iter().copied().collect());
+        assert!(taint.word(11).is_empty());
+        assert!(taint.control().is_empty());
+    }
+
+    #[test]
+    fn day5() {
+        let program = include_str!("../../input/2019/day5.txt");
+
+        // The first tests only use the input to patch an instruction whose
+        // result is overwritten before it's read, so the diagnostics don't
+        // depend on it.
+        let outputs = run(program, &[1]);
+        assert_eq!(outputs.len(), 10);
+        assert!(outputs.iter().all(|o| o.ends_with(": no inputs")));
+
+        // The later tests compare it and jump.
+        let outputs = run(program, &[5]);
+        assert_eq!(outputs.len(), 1);
+        assert!(outputs[0].ends_with(": input 0"));
+    }
+}
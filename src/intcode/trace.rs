@@ -0,0 +1,291 @@
+/*
+ * Instruction traces.
+ *
+ * When enabled on a machine, every executed instruction is logged along with
+ * the data it read and wrote, any input or output, and where execution
+ * continued, e.g. as formatted by `TraceEntry::format`:
+ *
+ *        0     0: in [21]                  in 8, write [21]=8
+ *        1     2: eq [21], 8, [20]         read [21]=8, write [20]=1
+ *        2     6: jt [20], 22              read [20]=1, jump 22
+ *
+ * Traces can also be exported as Chrome trace-event JSON, to be loaded into a
+ * trace viewer such as Perfetto or chrome://tracing. Each run of instructions
+ * up to a taken jump (a basic block, as executed) is a span, and each input
+ * and output is an instant event. Time is measured in steps, shown as one
+ * microsecond each.
+ */
+
+use serde_json::{json, Value};
+
+use super::disassembler;
+use super::opcode::Registry;
+use super::Word;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TraceEntry {
+    pub step: u64,
+    pub address: usize,
+    /// The instruction and its raw parameters.
+    pub words: Vec<Word>,
+    /// Data read through position mode parameters, as (address, value).
+    pub reads: Vec<(usize, Word)>,
+    /// Data written, as (address, value).
+    pub writes: Vec<(usize, Word)>,
+    pub input: Option<Word>,
+    pub output: Option<Word>,
+    /// The address of the next instruction, just past this one if it halted.
+    pub next: usize,
+}
+
+impl TraceEntry {
+    /// Whether execution continued somewhere other than the next instruction.
+    pub fn jumped(&self) -> bool {
+        self.next != self.address + self.words.len()
+    }
+
+    pub fn format(&self, registry: &Registry) -> String {
+        let (text, _) = disassembler::instruction(registry, &self.words, 0);
+
+        let mut effects = Vec::new();
+        if let Some(value) = self.input {
+            effects.push(format!("in {}", value));
+        }
+        for (address, value) in &self.reads {
+            effects.push(format!("read [{}]={}", address, value));
+        }
+        for (address, value) in &self.writes {
+            effects.push(format!("write [{}]={}", address, value));
+        }
+        if let Some(value) = self.output {
+            effects.push(format!("out {}", value));
+        }
+        if self.jumped() {
+            effects.push(format!("jump {}", self.next));
+        }
+
+        format!(
+            "{:>6} {:>5}: {:<24} {}",
+            self.step,
+            self.address,
+            text,
+            effects.join(", ")
+        )
+        .trim_end()
+        .to_string()
+    }
+}
+
+// A basic block being executed.
+#[derive(Clone, Debug)]
+struct Block {
+    step: u64,
+    start: usize,
+    end: usize,
+    instructions: u64,
+}
+
+/// Builds Chrome trace-event JSON from trace entries, given in the order
+/// they were executed.
+#[derive(Clone, Debug)]
+pub struct ChromeTrace {
+    events: Vec<Value>,
+    block: Option<Block>,
+}
+
+impl ChromeTrace {
+    pub fn new() -> ChromeTrace {
+        ChromeTrace {
+            events: vec![json!({
+                "name": "thread_name",
+                "ph": "M",
+                "pid": 0,
+                "tid": 0,
+                "args": { "name": "intcode" }
+            })],
+            block: None,
+        }
+    }
+
+    pub fn push(&mut self, entry: &TraceEntry) {
+        let block = self.block.get_or_insert(Block {
+            step: entry.step,
+            start: entry.address,
+            end: entry.address,
+            instructions: 0,
+        });
+        block.end = entry.address;
+        block.instructions += 1;
+
+        if let Some(value) = entry.input {
+            self.instant("in", entry, value);
+        }
+        if let Some(value) = entry.output {
+            self.instant("out", entry, value);
+        }
+        if entry.jumped() {
+            self.end_block();
+        }
+    }
+
+    /// The trace as a JSON object, ending the last block.
+    pub fn finish(mut self) -> Value {
+        self.end_block();
+        json!({ "traceEvents": self.events, "displayTimeUnit": "ns" })
+    }
+
+    fn instant(&mut self, name: &str, entry: &TraceEntry, value: Word) {
+        self.events.push(json!({
+            "name": format!("{} {}", name, value),
+            "cat": "io",
+            "ph": "i",
+            "s": "t",
+            "ts": entry.step,
+            "pid": 0,
+            "tid": 0,
+            "args": { "address": entry.address, "value": value }
+        }));
+    }
+
+    fn end_block(&mut self) {
+        if let Some(block) = self.block.take() {
+            self.events.push(json!({
+                "name": format!("block {}", block.start),
+                "cat": "block",
+                "ph": "X",
+                "ts": block.step,
+                "dur": block.instructions,
+                "pid": 0,
+                "tid": 0,
+                "args": {
+                    "start": block.start,
+                    "end": block.end,
+                    "instructions": block.instructions
+                }
+            }));
+        }
+    }
+}
+
+impl Default for ChromeTrace {
+    fn default() -> ChromeTrace {
+        ChromeTrace::new()
+    }
+}
+
+#[cfg(test)]
+mod test_trace {
+    use super::super::{parse, Machine};
+    use super::*;
+
+    #[test]
+    fn entries() {
+        let mut machine = Machine::new(&parse("3,9,1008,9,8,10,4,10,99,0,0"));
+        machine.set_trace(true);
+        machine.push_input(8);
+        assert_eq!(machine.run_to_halt(), Ok(vec![1]));
+
+        let trace = machine.take_trace();
+        let lines: Vec<String> = trace
+            .iter()
+            .map(|entry| entry.format(machine.registry()))
+            .collect();
+        assert_eq!(
+            lines,
+            vec![
+                "     0     0: in [9]                   in 8, write [9]=8",
+                "     1     2: eq [9], 8, [10]          read [9]=8, write [10]=1",
+                "     2     6: out [10]                 read [10]=1, out 1",
+                "     3     8: hlt",
+            ]
+        );
+        assert!(machine.trace().unwrap().is_empty());
+    }
+
+    #[test]
+    fn jump() {
+        let mut machine = Machine::new(&parse("1105,1,4,99,99"));
+        machine.set_trace(true);
+        machine.run_to_halt().unwrap();
+
+        let trace = machine.trace().unwrap();
+        assert!(trace[0].jumped());
+        assert_eq!(trace[0].next, 4);
+        assert_eq!(trace[1].address, 4);
+    }
+
+    #[test]
+    fn waiting_is_not_traced() {
+        let mut machine = Machine::new(&parse("3,0,99"));
+        machine.set_trace(true);
+        machine.run().unwrap();
+        assert!(machine.trace().unwrap().is_empty());
+
+        machine.push_input(5);
+        machine.run().unwrap();
+        assert_eq!(machine.trace().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn chrome_trace() {
+        // Outputs 1 if the input is 8, jumping over the code for 0.
+        let mut machine = Machine::new(&parse("3,12,1008,12,8,12,1005,12,11,104,0,104,1,99"));
+        machine.set_trace(true);
+        machine.push_input(8);
+        assert_eq!(machine.run_to_halt(), Ok(vec![1]));
+
+        let mut chrome = ChromeTrace::new();
+        for entry in machine.trace().unwrap() {
+            chrome.push(entry);
+        }
+        let events = chrome.finish()["traceEvents"].clone();
+        let summary: Vec<String> = events
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|e| format!("{} {} ts={} dur={}", e["ph"], e["name"], e["ts"], e["dur"]))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                r#""M" "thread_name" ts=null dur=null"#,
+                r#""i" "in 8" ts=0 dur=null"#,
+                r#""X" "block 0" ts=0 dur=3"#,
+                r#""i" "out 1" ts=3 dur=null"#,
+                r#""X" "block 11" ts=3 dur=2"#,
+            ]
+        );
+        assert_eq!(
+            events[2]["args"],
+            json!({ "start": 0, "end": 6, "instructions": 3 })
+        );
+    }
+
+    #[test]
+    fn chrome_trace_day5() {
+        // Every step is in exactly one block, and every output is an event.
+        let mut machine = Machine::new(&parse(include_str!("../../input/2019/day5.txt")));
+        machine.set_trace(true);
+        machine.push_input(5);
+        let outputs = machine.run_to_halt().unwrap();
+
+        let mut chrome = ChromeTrace::new();
+        for entry in machine.trace().unwrap() {
+            chrome.push(entry);
+        }
+        let trace = chrome.finish();
+        let events = trace["traceEvents"].as_array().unwrap();
+
+        let mut step = 0;
+        for block in events.iter().filter(|e| e["ph"] == "X") {
+            assert_eq!(block["ts"], step);
+            step += block["dur"].as_u64().unwrap();
+        }
+        assert_eq!(step, machine.steps());
+
+        let out: Vec<&Value> = events.iter().filter(|e| e["cat"] == "io").collect();
+        assert_eq!(out.len(), outputs.len() + 1);
+        assert_eq!(out[0]["name"], "in 5");
+        assert_eq!(out[1]["args"]["value"], outputs[0]);
+    }
+}
@@ -8,5 +8,11 @@ pub mod day2;
 pub mod day3;
 pub mod day4;
 pub mod day5;
+pub mod intcode;
+
+#[cfg(test)]
+mod conformance;
+#[cfg(test)]
+mod differential;
 
 aoc_lib! { year = 2019 }
@@ -0,0 +1,163 @@
+/*
+ * Tests for the `intcode` command-line tool, run as a separate process.
+ */
+
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+// Run `intcode` with these arguments and stdin.
+fn intcode(args: &[&str], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_intcode"))
+        .args(args)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[test]
+fn record_and_replay() {
+    let session = env::temp_dir().join(format!("intcode-session-{}.txt", std::process::id()));
+    let session = session.to_str().unwrap();
+    let program = "input/2019/day5.txt";
+
+    // Typed input is read once the queued input runs out.
+    let output = intcode(&["--record", session, program], "oops\n5\n");
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "6959377\n");
+    assert!(stderr(&output).contains("invalid input: \"oops\""));
+    assert_eq!(
+        fs::read_to_string(session).unwrap(),
+        "# step, direction, value\n0 in 5\n100 out 6959377\n"
+    );
+
+    let output = intcode(&["--replay", session, program], "");
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        stderr(&output),
+        format!("replayed 2 records from {}\n", session)
+    );
+
+    // The same session doesn't match Day 2's program.
+    let output = intcode(&["--replay", session, "input/2019/day2.txt"], "");
+    assert_eq!(output.status.code(), Some(1));
+    fs::remove_file(session).unwrap();
+
+    let output = intcode(&["--replay", session, program], "");
+    assert_eq!(output.status.code(), Some(2));
+    let output = intcode(&["--replay", session, "--input", "1", program], "");
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(stderr(&output), "--input can't be used with --replay\n");
+}
+
+#[test]
+fn sparse_memory() {
+    // Only the pages that exist are printed, not the whole address space.
+    let output = intcode(&["--sparse", "--memory", "tests/cli/huge.txt"], "");
+    assert!(output.status.success(), "{}", stderr(&output));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert!(lines[0].starts_with("0: 1101,7,0,1000000000000,99,0,"));
+    assert_eq!(lines[1], "1000000000000: 7");
+}
+
+#[test]
+fn explore() {
+    let output = intcode(&["--explore", "7,8", "input/2019/day5.txt"], "");
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("  inputs: 8\n"));
+
+    // Options for the ordinary run are rejected rather than ignored.
+    for option in ["--trace", "--memory", "--taint"] {
+        let output = intcode(&["--explore", "1", option, "tests/cli/huge.txt"], "");
+        assert_eq!(output.status.code(), Some(2));
+        assert_eq!(
+            stderr(&output),
+            format!("{} can't be used with --explore\n", option)
+        );
+    }
+    let output = intcode(
+        &["--gdb", "0", "--core", "core.txt", "tests/cli/huge.txt"],
+        "",
+    );
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(stderr(&output), "--core can't be used with --gdb\n");
+    let output = intcode(&["--gdb", "0", "--explore", "1", "tests/cli/huge.txt"], "");
+    assert_eq!(stderr(&output), "--gdb can't be used with --explore\n");
+}
+
+#[test]
+fn explore_input_loop() {
+    // Reads input forever, so exploring it stops at the path limit.
+    let args = [
+        "--explore",
+        "1,2",
+        "--max-paths",
+        "5",
+        "tests/cli/input_loop.txt",
+    ];
+    let output = intcode(&args, "");
+    assert!(output.status.success(), "{}", stderr(&output));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(stdout.matches("inputs:").count(), 5);
+    assert!(stdout.ends_with("stopped after 5 paths\n"));
+
+    let output = intcode(&["--max-paths", "5", "tests/cli/input_loop.txt"], "");
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(stderr(&output), "--max-paths needs --explore\n");
+}
+
+#[test]
+fn overflow() {
+    // Faults rather than panicking, for both add and multiply.
+    for program in ["tests/cli/add_overflow.txt", "tests/cli/mul_overflow.txt"] {
+        let output = intcode(&[program], "");
+        assert_eq!(output.status.code(), Some(1), "{}", stderr(&output));
+        assert_eq!(
+            stderr(&output),
+            "faulted at address 0 after 0 steps with 0 outputs: arithmetic overflow at address 0\n"
+        );
+    }
+}
+
+#[test]
+fn dense_address_limit() {
+    // Huge addresses fault or are rejected unless memory is sparse.
+    let output = intcode(&["tests/cli/huge.txt"], "");
+    assert_eq!(output.status.code(), Some(1), "{}", stderr(&output));
+    assert!(stderr(&output)
+        .ends_with("address 1000000000000 at address 0 is past the dense memory limit\n"));
+
+    let output = intcode(&["--patch", "1000000000000=1", "tests/cli/huge.txt"], "");
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(
+        stderr(&output),
+        "can't patch address 1000000000000 without --sparse\n"
+    );
+    let output = intcode(
+        &[
+            "--sparse",
+            "--patch",
+            "1000000000000=1",
+            "tests/cli/huge.txt",
+        ],
+        "",
+    );
+    assert!(output.status.success(), "{}", stderr(&output));
+}
@@ -0,0 +1,9 @@
+# Output 1 if the input is 8, otherwise 0, then the input times 2.
+start:  in [value]
+        eq [value], 8, [result]
+        out [result]
+        mul [value], 2, [result]
+        out [result]
+        hlt
+value:  data 0
+result: data 0
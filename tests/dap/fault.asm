@@ -0,0 +1,3 @@
+# Outputs 7, then runs into data.
+        out 7
+        data 42
@@ -0,0 +1,4 @@
+# Counts forever.
+loop:   add [count], 1, [count]
+        jt 1, loop
+count:  data 0
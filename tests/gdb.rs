@@ -0,0 +1,291 @@
+/*
+ * GDB remote protocol integration tests.
+ *
+ * Each test serves a machine on a local socket and talks to it the way a
+ * debugger would, writing and parsing the raw packets itself.
+ */
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::Duration;
+
+use advent_of_code_2019_rust::intcode::device::Clock;
+use advent_of_code_2019_rust::intcode::gdb::Stub;
+use advent_of_code_2019_rust::intcode::memory::Backend;
+use advent_of_code_2019_rust::intcode::{parse, Machine, Word};
+
+struct Client {
+    stream: TcpStream,
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum, &byte| sum.wrapping_add(byte))
+}
+
+impl Client {
+    fn connect(address: &str) -> Client {
+        let stream = TcpStream::connect(address).unwrap();
+        stream.set_nodelay(true).unwrap();
+        Client { stream }
+    }
+
+    fn byte(&mut self) -> u8 {
+        let mut byte = [0];
+        self.stream.read_exact(&mut byte).unwrap();
+        byte[0]
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        self.stream.write_all(bytes).unwrap();
+    }
+
+    // Send a packet and check it's acknowledged.
+    fn send(&mut self, data: &str) {
+        self.write(format!("${}#{:02x}", data, checksum(data.as_bytes())).as_bytes());
+        assert_eq!(self.byte(), b'+');
+    }
+
+    // Receive a packet, check its checksum and acknowledge it.
+    fn receive(&mut self) -> String {
+        assert_eq!(self.byte(), b'$');
+        let mut data = Vec::new();
+        loop {
+            match self.byte() {
+                b'#' => break,
+                byte => data.push(byte),
+            }
+        }
+        let sum = [self.byte(), self.byte()];
+        let sum = u8::from_str_radix(std::str::from_utf8(&sum).unwrap(), 16).unwrap();
+        assert_eq!(sum, checksum(&data));
+        self.write(b"+");
+
+        String::from_utf8(data).unwrap()
+    }
+
+    // Send a command and return its replies, up to and including the first
+    // that isn't console output.
+    fn command(&mut self, data: &str) -> Vec<String> {
+        self.send(data);
+        let mut replies = Vec::new();
+        loop {
+            let reply = self.receive();
+            let done = !reply.starts_with('O') || reply == "OK";
+            replies.push(reply);
+            if done {
+                return replies;
+            }
+        }
+    }
+
+    fn reply(&mut self, data: &str) -> String {
+        self.command(data).pop().unwrap()
+    }
+}
+
+// Serve a machine on a local socket, returning a connected client and the
+// server thread, which finishes with the stub once the client detaches.
+fn serve(machine: Machine) -> (Client, thread::JoinHandle<Stub>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let server = thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        stream.set_nodelay(true).unwrap();
+        let mut stub = Stub::new(machine);
+        stub.serve(stream).unwrap();
+        stub
+    });
+
+    (Client::connect(&address.to_string()), server)
+}
+
+fn connect(program: &str, input: &[Word]) -> (Client, thread::JoinHandle<Stub>) {
+    let mut machine = Machine::new(&parse(program));
+    machine.extend_input(input.iter().copied());
+    serve(machine)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[test]
+fn registers_and_memory() {
+    let (mut client, server) = connect("1101,2,3,5,99,0", &[]);
+
+    assert_eq!(client.reply("qSupported:swbreak+"), "PacketSize=1000");
+    assert_eq!(client.reply("?"), "S05");
+    assert_eq!(client.reply("g"), "0000000000000000");
+    // Word 1 is bytes 8 to 15.
+    assert_eq!(client.reply("m8,8"), "0200000000000000");
+    assert_eq!(client.reply("m0,2"), "4d04");
+    assert_eq!(client.reply("m30,8"), "0000000000000000");
+
+    // Change the first operand from 2 to -1.
+    assert_eq!(client.reply("M8,8:ffffffffffffffff"), "OK");
+    assert_eq!(client.reply("s"), "S05");
+    assert_eq!(client.reply("p0"), "0400000000000000");
+    assert_eq!(client.reply("p1"), "E00");
+    assert_eq!(client.reply("m28,8"), "0200000000000000");
+
+    assert_eq!(client.reply("c"), "W00");
+    assert_eq!(client.reply("m0,1"), "4d");
+    assert_eq!(client.reply("vMustReplyEmpty"), "");
+    assert_eq!(client.reply("D"), "OK");
+
+    let stub = server.join().unwrap();
+    assert!(stub.machine().is_halted());
+    assert_eq!(stub.machine().peek(5), 2);
+}
+
+#[test]
+fn memory_limits() {
+    let (mut client, server) = connect("99,0", &[]);
+
+    // Reads are cut off at half the packet size, as each byte is two digits.
+    assert_eq!(client.reply("m0,ffffffffffffffff").len(), 0x1000);
+    assert_eq!(client.reply("m8,ffffffffffffffff"), "E01");
+    assert_eq!(client.reply("mffffffffffffffff,2"), "E01");
+
+    // Writes can't grow dense memory.
+    assert_eq!(client.reply("M8,8:0100000000000000"), "OK");
+    assert_eq!(client.reply("M10,1:01"), "E01");
+    assert_eq!(client.reply("M1000000000000,1:01"), "E01");
+    assert_eq!(client.reply("Mffffffffffffffff,1:01"), "E01");
+    assert_eq!(client.reply("D"), "OK");
+
+    let stub = server.join().unwrap();
+    assert_eq!(stub.machine().memory().len(), 2);
+    assert_eq!(stub.machine().peek(1), 1);
+
+    // Sparse memory can be written anywhere.
+    let mut machine = Machine::new(&parse("99"));
+    machine.set_backend(Backend::Sparse);
+    let (mut client, server) = serve(machine);
+    assert_eq!(client.reply("M1000000000000,1:2a"), "OK");
+    assert_eq!(client.reply("m1000000000000,1"), "2a");
+    assert_eq!(client.reply("D"), "OK");
+    assert_eq!(server.join().unwrap().machine().peek(0x200000000000), 42);
+}
+
+#[test]
+fn devices() {
+    // Memory under a device is read and written directly, bypassing it.
+    let mut machine = Machine::new(&parse("4,3,99,0"));
+    machine.map_device(3, Box::new(Clock::new()));
+    let (mut client, server) = serve(machine);
+    assert_eq!(client.reply("M18,8:2a00000000000000"), "OK");
+    assert_eq!(client.reply("m18,8"), "2a00000000000000");
+    assert_eq!(client.reply("D"), "OK");
+
+    let mut machine = server.join().unwrap().machine().clone();
+    assert_eq!(machine.peek(3), 42);
+    // The clock wasn't set to 42, so still outputs its first tick.
+    assert_eq!(machine.run_to_halt(), Ok(vec![0]));
+}
+
+#[test]
+fn breakpoints_and_output() {
+    // Outputs 1, 2, 3 then halts.
+    let (mut client, server) = connect("104,1,104,2,104,3,99", &[]);
+
+    assert_eq!(client.reply("Z0,20,1"), "OK");
+    assert_eq!(client.reply("Z0,30,1"), "OK");
+    // "1\n" and "2\n" on the console, then stops before address 4.
+    assert_eq!(client.command("c"), vec!["O310a", "O320a", "S05"]);
+    assert_eq!(client.reply("g"), "0400000000000000");
+
+    assert_eq!(client.reply("z0,30,1"), "OK");
+    assert_eq!(client.command("c"), vec!["O330a", "W00"]);
+    assert_eq!(client.reply("?"), "W00");
+    assert_eq!(client.reply("Z2,0,1"), "");
+    client.send("k");
+
+    assert_eq!(server.join().unwrap().outputs(), &[1, 2, 3]);
+}
+
+#[test]
+fn input_and_faults() {
+    // Reads an input, outputs it, then faults on opcode 42.
+    let (mut client, server) = connect("3,7,4,7,42,99,99,0", &[]);
+
+    assert_eq!(client.reply("c"), "S15");
+    // monitor input 9
+    assert_eq!(client.reply(&format!("qRcmd,{}", hex(b"input 9"))), "OK");
+    assert_eq!(client.command("c"), vec!["O390a", "S04"]);
+    assert_eq!(client.reply("g"), "0400000000000000");
+
+    // Resume past the faulting instruction.
+    assert_eq!(client.reply("c28"), "W00");
+    assert_eq!(client.reply("qRcmd,00"), "E01");
+    assert_eq!(client.reply("D"), "OK");
+
+    assert_eq!(server.join().unwrap().machine().pointer(), 5);
+}
+
+#[test]
+fn interrupt() {
+    // Jumps back to itself forever.
+    let (mut client, server) = connect("1105,1,0", &[]);
+
+    client.send("c");
+    thread::sleep(Duration::from_millis(50));
+    client.write(&[0x03]);
+    assert_eq!(client.receive(), "S02");
+    assert_eq!(client.reply("?"), "S02");
+    assert_eq!(client.reply("g"), "0000000000000000");
+    assert_eq!(client.reply("D"), "OK");
+
+    assert!(server.join().unwrap().machine().steps() > 0);
+}
+
+#[test]
+fn checksums() {
+    let (mut client, server) = connect("99", &[]);
+
+    // A corrupted packet is rejected and has to be resent.
+    client.write(b"$g#00");
+    assert_eq!(client.byte(), b'-');
+    client.write(b"$g#67");
+    assert_eq!(client.byte(), b'+');
+    assert_eq!(client.receive(), "0000000000000000");
+
+    // Escaped bytes are decoded: "}\x10" is '0'.
+    let data = b"m}\x10,1";
+    client.write(b"$");
+    client.write(data);
+    client.write(format!("#{:02x}", checksum(data)).as_bytes());
+    assert_eq!(client.byte(), b'+');
+    assert_eq!(client.receive(), "63");
+
+    drop(client);
+    assert_eq!(server.join().unwrap().machine().peek(0), 99);
+}
+
+#[test]
+fn intcode_gdb() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_intcode"))
+        .args(["--gdb", "0", "tests/gdb/count.txt"])
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    // "waiting for a debugger on 127.0.0.1:<port>"
+    let mut stderr = BufReader::new(child.stderr.take().unwrap());
+    let mut line = String::new();
+    stderr.read_line(&mut line).unwrap();
+    let address = line.trim().rsplit(' ').next().unwrap();
+
+    let mut client = Client::connect(address);
+    assert_eq!(client.reply("Z0,10,1"), "OK");
+    assert_eq!(client.command("c"), vec!["O310a", "S05"]);
+    assert_eq!(client.reply("D"), "OK");
+
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "1\n");
+}